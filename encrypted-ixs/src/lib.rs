@@ -1,77 +1,263 @@
+// Arcis supports neither slice ranges nor iterators, so circuits copy and scan
+// arrays with plain indexed loops.
+#![allow(clippy::manual_memcpy, clippy::needless_range_loop)]
+
 use arcis_imports::*;
 
 #[encrypted]
 mod circuits {
     use arcis_imports::*;
 
+    // Coordinate of the cells of a fleet that is not placed yet. Boards are at most
+    // 16x16, so it never matches a real cell.
+    const EMPTY_CELL: u8 = 255;
+
+    // Every preset fits the same 7 ship slots of at most 5, 4, 3, 3, 2, 1 and 1 cells.
+    // Cell `i` belongs to slot `SHIP_OF_CELL[i]` and sits `CELL_OFFSET[i]` cells away
+    // from that ship's bow.
+    const MAX_SHIPS: usize = 7;
+    const MAX_CELLS: usize = 19;
+    const SHIP_OF_CELL: [usize; MAX_CELLS] =
+        [0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 3, 3, 3, 4, 4, 5, 6];
+    const CELL_OFFSET: [u8; MAX_CELLS] = [0, 1, 2, 3, 4, 0, 1, 2, 3, 0, 1, 2, 0, 1, 2, 0, 1, 0, 0];
+    // Ship length per slot of each `FleetPreset`, 0 leaves the slot empty.
+    const CLASSIC: [u8; MAX_SHIPS] = [5, 4, 3, 3, 2, 0, 0];
+    const MILTON_BRADLEY: [u8; MAX_SHIPS] = [5, 4, 3, 2, 2, 1, 1];
+    const SMALL: [u8; MAX_SHIPS] = [3, 2, 2, 0, 0, 0, 0];
+    // Revealed by `take_turn` when the shot did not sink a ship.
+    const NO_SHIP: u8 = 255;
+    // Every ciphertext of the fleet state travels in the callback transaction, so
+    // each fleet is packed a byte at a time into u128 words: the row and column of
+    // every cell, then the health of every ship.
+    const FLEET_WORDS: usize = 3;
+    const HEALTH_BYTE: usize = 2 * MAX_CELLS;
+
     pub struct PlayerShipFleet {
-        player_1: [[u8; 2]; 17], // Array of [row, col] coordinates for Player 1
-        player_2: [[u8; 2]; 17], // Array of [row, col] coordinates for Player 2
+        player_1: [u128; FLEET_WORDS], // Player 1's packed cells and ship health
+        player_2: [u128; FLEET_WORDS], // Player 2's packed cells and ship health
+        player_1_salt: u128,           // Salt of Player 1's placement commitment
+        player_2_salt: u128,           // Salt of Player 2's placement commitment
+    }
+
+    pub struct ShipPlacement {
+        bow: u8,        // `row * 16 + col` of the ship's bow
+        vertical: bool, // extends down the rows instead of across the columns
     }
 
     pub struct PlaceShipsInputs {
-        is_player_1: bool,
-        fleet_location: [[u8; 2]; 17],
+        ships: [ShipPlacement; MAX_SHIPS], // one per slot, ignored for the preset's empty slots
+        salt: u128,                        // hashed with the fleet's cells into the commitment
     }
 
-    pub struct TakeTurnInputs {
-        is_player_1: bool,
-        guess: [u8; 2],
+    // Ship lengths per slot of the preset stored in `GameData::fleet_preset`.
+    fn ship_lengths(fleet_preset: u8) -> [u8; MAX_SHIPS] {
+        if fleet_preset == 1 {
+            MILTON_BRADLEY
+        } else if fleet_preset == 2 {
+            SMALL
+        } else {
+            CLASSIC
+        }
+    }
+
+    fn pack_fleet(
+        cells: [[u8; 2]; MAX_CELLS],
+        ship_health: [u8; MAX_SHIPS],
+    ) -> [u128; FLEET_WORDS] {
+        let mut bytes = [0u8; 16 * FLEET_WORDS];
+        for i in 0..MAX_CELLS {
+            bytes[2 * i] = cells[i][0];
+            bytes[2 * i + 1] = cells[i][1];
+        }
+        for ship in 0..MAX_SHIPS {
+            bytes[HEALTH_BYTE + ship] = ship_health[ship];
+        }
+
+        // Little-endian, the first byte of each word is its lowest.
+        let mut words = [0u128; FLEET_WORDS];
+        for w in 0..FLEET_WORDS {
+            for b in 0..16 {
+                words[w] = words[w] * 256 + bytes[16 * w + 15 - b] as u128;
+            }
+        }
+        words
+    }
+
+    fn unpack_fleet(words: [u128; FLEET_WORDS]) -> ([[u8; 2]; MAX_CELLS], [u8; MAX_SHIPS]) {
+        let mut bytes = [0u8; 16 * FLEET_WORDS];
+        for w in 0..FLEET_WORDS {
+            let mut word = words[w];
+            for b in 0..16 {
+                bytes[16 * w + b] = (word % 256) as u8;
+                word /= 256;
+            }
+        }
+
+        let mut cells = [[0u8; 2]; MAX_CELLS];
+        for i in 0..MAX_CELLS {
+            cells[i] = [bytes[2 * i], bytes[2 * i + 1]];
+        }
+        let mut ship_health = [0u8; MAX_SHIPS];
+        for ship in 0..MAX_SHIPS {
+            ship_health[ship] = bytes[HEALTH_BYTE + ship];
+        }
+        (cells, ship_health)
     }
 
     #[instruction]
-    pub fn init_player_ship_fleet_location(mxe: Mxe) -> Enc<Mxe, PlayerShipFleet> {
+    pub fn init_player_ship_fleet_location(
+        fleet_preset: u8, // every ship starts with its full length as health
+        mxe: Mxe,
+    ) -> Enc<Mxe, PlayerShipFleet> {
+        let empty_fleet = pack_fleet([[EMPTY_CELL; 2]; MAX_CELLS], ship_lengths(fleet_preset));
         mxe.from_arcis(PlayerShipFleet {
-            player_1: [[255; 2]; 17],
-            player_2: [[255; 2]; 17],
+            player_1: empty_fleet,
+            player_2: empty_fleet,
+            player_1_salt: 0,
+            player_2_salt: 0,
         })
     }
 
     #[instruction]
     pub fn place_ships(
+        is_player_1: bool, // supplied by the program from the signer, not the client
+        board_size: u8,    // rows and columns of this game's board
+        fleet_preset: u8,  // decides which cells of each slot are part of a ship
         input_ctxt: Enc<Shared, PlaceShipsInputs>,
         player_ship_fleet_ctxt: Enc<Mxe, PlayerShipFleet>,
-    ) -> Enc<Mxe, PlayerShipFleet> {
+    ) -> (Enc<Mxe, PlayerShipFleet>, bool, bool) {
         let player_inputs = input_ctxt.to_arcis();
         let mut ship_fleet_data = player_ship_fleet_ctxt.to_arcis();
 
-        if player_inputs.is_player_1 {
-            ship_fleet_data.player_1 = player_inputs.fleet_location;
-        } else {
-            ship_fleet_data.player_2 = player_inputs.fleet_location;
+        // Expand every ship into its cells. Ships are straight lines of the preset's
+        // lengths by construction, so only bounds and overlaps are left to check.
+        // Cells past a ship's length stay `EMPTY_CELL`, like an unplaced fleet.
+        let ship_lengths = ship_lengths(fleet_preset);
+        let mut fleet_location = [[EMPTY_CELL; 2]; MAX_CELLS];
+        let mut is_valid = true;
+        for i in 0..MAX_CELLS {
+            let ship = &player_inputs.ships[SHIP_OF_CELL[i]];
+            let is_used = CELL_OFFSET[i] < ship_lengths[SHIP_OF_CELL[i]];
+            // The bow is below 16 in both directions, so neither coordinate can wrap.
+            let bow_row = ship.bow / 16;
+            let bow_col = ship.bow % 16;
+            let (row, col) = if ship.vertical {
+                (bow_row + CELL_OFFSET[i], bow_col)
+            } else {
+                (bow_row, bow_col + CELL_OFFSET[i])
+            };
+
+            let in_bounds = (row < board_size) & (col < board_size);
+            is_valid &= !is_used | in_bounds;
+            if is_used {
+                fleet_location[i] = [row, col];
+            }
+        }
+
+        // Arcis loops need constant bounds, so each pair is picked out inside the loop.
+        for i in 0..MAX_CELLS {
+            for j in 0..MAX_CELLS {
+                if i < j {
+                    let overlaps = (fleet_location[i][0] != EMPTY_CELL)
+                        & (fleet_location[i][0] == fleet_location[j][0])
+                        & (fleet_location[i][1] == fleet_location[j][1]);
+                    is_valid &= !overlaps;
+                }
+            }
+        }
+
+        // A rejected fleet leaves the stored one untouched. No shot has been fired
+        // yet, so every ship still has its full length as health.
+        let placed_fleet = pack_fleet(fleet_location, ship_lengths);
+        if is_valid {
+            if is_player_1 {
+                ship_fleet_data.player_1 = placed_fleet;
+                ship_fleet_data.player_1_salt = player_inputs.salt;
+            } else {
+                ship_fleet_data.player_2 = placed_fleet;
+                ship_fleet_data.player_2_salt = player_inputs.salt;
+            }
         }
 
-        player_ship_fleet_ctxt.owner.from_arcis(ship_fleet_data)
+        (
+            player_ship_fleet_ctxt.owner.from_arcis(ship_fleet_data),
+            is_player_1.reveal(),
+            is_valid.reveal(),
+        )
     }
 
     #[instruction]
     pub fn take_turn(
-        input_ctxt: TakeTurnInputs, // this do not have to be encrypted
+        is_player_1: bool, // derived by the program from `game_state`
+        row: u8,           // the guess is public, only the fleet is secret
+        col: u8,
         player_ship_fleet_ctxt: Enc<Mxe, PlayerShipFleet>,
-    ) -> (Enc<Mxe, PlayerShipFleet>, bool) {
-        let is_player_1 = input_ctxt.is_player_1;
-        let [row, col] = input_ctxt.guess;
+    ) -> (Enc<Mxe, PlayerShipFleet>, bool, u8) {
         let mut ship_fleet_data = player_ship_fleet_ctxt.to_arcis();
 
         let mut was_hit = false;
+        let mut hit_ship = NO_SHIP;
 
-        let enemy_fleet_location = if is_player_1 {
-            &mut ship_fleet_data.player_2
+        let (enemy_fleet_location, mut enemy_ship_health) = unpack_fleet(if is_player_1 {
+            ship_fleet_data.player_2
         } else {
-            &mut ship_fleet_data.player_1
-        };
-        for i in 0..enemy_fleet_location.len() {
+            ship_fleet_data.player_1
+        });
+        // Repeated shots are rejected on-chain, so a hit cell keeps its coordinates
+        // and only the health of the ship it belongs to drops. Cells outside the
+        // preset are `EMPTY_CELL` and never hit.
+        for i in 0..MAX_CELLS {
             let ship_loc = enemy_fleet_location[i];
             if ship_loc[0] == row && ship_loc[1] == col {
                 was_hit = true;
+                hit_ship = SHIP_OF_CELL[i] as u8;
+
+                enemy_ship_health[SHIP_OF_CELL[i]] -= 1;
+            }
+        }
 
-                enemy_fleet_location[i] = [11, 11];
+        // Only reveal which ship was hit once it has sunk.
+        let mut sunk_ship = NO_SHIP;
+        for ship in 0..MAX_SHIPS {
+            if hit_ship == ship as u8 && enemy_ship_health[ship] == 0 {
+                sunk_ship = ship as u8;
             }
         }
 
+        let enemy_fleet = pack_fleet(enemy_fleet_location, enemy_ship_health);
+        if is_player_1 {
+            ship_fleet_data.player_2 = enemy_fleet;
+        } else {
+            ship_fleet_data.player_1 = enemy_fleet;
+        }
+
         (
             player_ship_fleet_ctxt.owner.from_arcis(ship_fleet_data),
             was_hit.reveal(),
+            sunk_ship.reveal(),
+        )
+    }
+
+    #[instruction]
+    pub fn reveal_fleets(
+        player_ship_fleet_ctxt: Enc<Mxe, PlayerShipFleet>,
+    ) -> ([[u8; 2]; 2 * MAX_CELLS], u128, u128) {
+        let ship_fleet_data = player_ship_fleet_ctxt.to_arcis();
+
+        // Hits only lower ship health, so the cells are still the fleets as placed.
+        let (player_1_cells, _) = unpack_fleet(ship_fleet_data.player_1);
+        let (player_2_cells, _) = unpack_fleet(ship_fleet_data.player_2);
+        let mut fleets = [[0; 2]; 2 * MAX_CELLS];
+        for i in 0..MAX_CELLS {
+            fleets[i] = [player_1_cells[i][0].reveal(), player_1_cells[i][1].reveal()];
+            fleets[MAX_CELLS + i] = [player_2_cells[i][0].reveal(), player_2_cells[i][1].reveal()];
+        }
+
+        // The salts let anyone recompute the placement commitments.
+        (
+            fleets,
+            ship_fleet_data.player_1_salt.reveal(),
+            ship_fleet_data.player_2_salt.reveal(),
         )
     }
 }
@@ -1,4 +1,16 @@
+// The IDL instructions `#[program]` generates next to the module still call the
+// deprecated `AccountInfo::realloc`.
+#![allow(deprecated)]
+
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::system_program::{transfer, Transfer};
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token::{
+        close_account, transfer_checked, CloseAccount, Mint, Token, TokenAccount, TransferChecked,
+    },
+};
 use arcium_anchor::{
     comp_def_offset, derive_cluster_pda, derive_comp_def_pda, derive_comp_pda, derive_execpool_pda,
     derive_mempool_pda, derive_mxe_pda, init_comp_def, queue_computation, ComputationOutputs,
@@ -22,6 +34,46 @@ use arcium_macros::{
 const COMP_DEF_OFFSET_INIT_PLAYER_SHIPS: u32 = comp_def_offset("init_player_ship_fleet_location");
 const COMP_DEF_OFFSET_PLACE_SHIPS: u32 = comp_def_offset("place_ships");
 const COMP_DEF_OFFSET_TAKE_TURN: u32 = comp_def_offset("take_turn");
+const COMP_DEF_OFFSET_REVEAL_FLEETS: u32 = comp_def_offset("reveal_fleets");
+
+// Boards are square, `board_size` cells wide. The circuits mark unplaced cells with
+// 255, which no coordinate of a 16x16 board reaches.
+const MIN_BOARD_SIZE: u8 = 8;
+const MAX_BOARD_SIZE: u8 = 16;
+// Ship slots and cells every `FleetPreset` fits in, slots hold at most 5, 4, 3, 3, 2,
+// 1 and 1 cells. Cells a preset leaves empty stay `[255, 255]`.
+const MAX_FLEET_SHIPS: usize = 7;
+const MAX_FLEET_CELLS: usize = 19;
+// `PlayerShipFleet` holds for each player the `[row, col]` cells and a health counter
+// per ship, packed a byte at a time into 3 u128 words, and the commitment salt. Every
+// scalar is its own ciphertext and the whole state must fit the callback transaction.
+const FLEET_STATE_WORDS: usize = 3;
+const FLEET_STATE_CIPHERTEXTS: usize = 2 * (FLEET_STATE_WORDS + 1);
+// Byte length of an `Enc<Mxe, PlayerShipFleet>` output: 16 byte nonce followed by the ciphertexts.
+const FLEET_STATE_OUTPUT_LEN: usize = 16 + FLEET_STATE_CIPHERTEXTS * 32;
+// Location of `encrypted_fleet_state` inside the `GameData` account: discriminator + preceding fields.
+const FLEET_STATE_OFFSET: u32 = 8 + 148;
+const FLEET_STATE_SIZE: u32 = (FLEET_STATE_CIPHERTEXTS * 32) as u32;
+
+// Protocol fees are quoted in basis points of the pot and capped at 10%.
+const MAX_FEE_BPS: u16 = 1_000;
+
+// Bits of `ProgramConfig::allowed_modes`.
+const MODE_INVITE: u8 = 1 << 0; // `new_game` with a named opponent
+const MODE_OPEN: u8 = 1 << 1; // `open_game` lobby games
+const MODE_WAGER: u8 = 1 << 2; // games with a non-zero wager
+const MODE_RANKED: u8 = 1 << 3; // games that move Elo ratings
+
+// Elo rating of a new profile and the most points a single game can move.
+const INITIAL_RATING: u32 = 1200;
+const ELO_K: u32 = 32;
+// Expected score in basis points of the higher rated player, for rating differences of
+// 0, 25, ..., 800: `10_000 / (1 + 10^(-diff / 400))` rounded.
+const ELO_EXPECTED_BPS: [u32; 33] = [
+    5000, 5359, 5715, 6063, 6401, 6725, 7034, 7325, 7597, 7850, 8083, 8296, 8490, 8666, 8823, 8965,
+    9091, 9203, 9302, 9390, 9468, 9536, 9595, 9648, 9693, 9733, 9768, 9799, 9825, 9848, 9868, 9886,
+    9901,
+];
 
 declare_id!("HVaMfas33TSAihSxJUvDTpLPnXzHsW4WcD67FKAUDHQ2");
 
@@ -34,37 +86,125 @@ pub mod confidential_battleship_game {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new_game(
         ctx: Context<NewGame>,
+        game_id: u64, // Client-chosen id, unique per creator.
         player_2_pubkey: Pubkey,
         player_1_arcium_pubkey: [u8; 32],
-        player_2_arcium_pubkey: [u8; 32],
-        computation_offset: u64,
-        mxe_nonce: u128, // Nonce for the MXE to create the initial state.
+        player_2_arcium_pubkey: [u8; 32], // Suggested key, the invitee may replace it.
+        move_timeout: i64,                // Seconds per placement or turn, 0 for the default.
+        wager: u64,                       // Per-player stake in lamports or `wager_mint` units.
+        ranked: bool,                     // Whether the result moves both players' ratings.
+        board_size: u8,                   // Rows and columns of the board, 8 to 16.
+        fleet_preset: FleetPreset,        // Ships each player places.
     ) -> Result<()> {
+        // Inviting yourself would let one wallet farm wins, ratings and wagers.
+        require_keys_neq!(
+            player_2_pubkey,
+            ctx.accounts.payer.key(),
+            BattleShipErrorCode::UnauthorizedPlayer
+        );
+        init_game(
+            ctx.accounts,
+            &ctx.bumps,
+            MODE_INVITE,
+            game_id,
+            player_1_arcium_pubkey,
+            move_timeout,
+            wager,
+            ranked,
+            board_size,
+            fleet_preset,
+        )?;
+
+        // Nothing is queued until `player_2` accepts the invite.
         let game_account = &mut ctx.accounts.game_account;
-        game_account.player_1 = ctx.accounts.payer.key();
         game_account.player_2 = player_2_pubkey;
-        game_account.player_1_arcium_pubkey = player_1_arcium_pubkey;
         game_account.player_2_arcium_pubkey = player_2_arcium_pubkey;
-        game_account.game_state = GameState::PlacingShips;
-        game_account.player_1_ships_left = 17;
-        game_account.player_2_ships_left = 17;
-        game_account.bump = ctx.bumps.game_account;
+        game_account.game_state = GameState::InvitePending;
+
+        emit!(InviteSent {
+            game_pda: game_account.key(),
+            game_id,
+            player_1: game_account.player_1,
+            player_2: game_account.player_2,
+        });
 
-        // Queue the computation to initialize the empty, encrypted fleet state.
-        let args = vec![Argument::PlaintextU128(mxe_nonce)];
+        Ok(())
+    }
 
-        queue_computation(
+    pub fn accept_invite(
+        ctx: Context<JoinGame>,
+        computation_offset: u64,
+        player_2_arcium_pubkey: Option<[u8; 32]>, // Replaces the key suggested by `player_1`.
+        mxe_nonce: u128,                          // Nonce for the MXE to create the initial state.
+    ) -> Result<()> {
+        let game_account = &mut ctx.accounts.game_account;
+        require!(
+            game_account.game_state == GameState::InvitePending,
+            BattleShipErrorCode::InvalidGameState
+        );
+        require!(
+            ctx.accounts.payer.key() == game_account.player_2,
+            BattleShipErrorCode::UnauthorizedPlayer
+        );
+
+        if let Some(player_2_arcium_pubkey) = player_2_arcium_pubkey {
+            game_account.player_2_arcium_pubkey = player_2_arcium_pubkey;
+        }
+
+        start_placement(
             ctx.accounts,
+            ctx.bumps.player_profile,
             computation_offset,
-            args,
-            vec![CallbackAccount {
-                pubkey: ctx.accounts.game_account.key(),
-                is_writable: true,
-            }],
-            None,
-        )?;
+            mxe_nonce,
+        )
+    }
+
+    pub fn decline_invite(ctx: Context<CancelInvite>) -> Result<()> {
+        let game_account = &mut ctx.accounts.game_account;
+        require!(
+            game_account.game_state == GameState::InvitePending,
+            BattleShipErrorCode::InvalidGameState
+        );
+        require!(
+            ctx.accounts.player.key() == game_account.player_2,
+            BattleShipErrorCode::UnauthorizedPlayer
+        );
+
+        game_account.game_state = GameState::Cancelled;
+
+        emit!(GameCancelled {
+            game_pda: game_account.key(),
+            cancelled_by: Some(game_account.player_2),
+        });
+
+        Ok(())
+    }
+
+    pub fn cancel_invite(ctx: Context<CancelInvite>) -> Result<()> {
+        let game_account = &mut ctx.accounts.game_account;
+        // Open games can be withdrawn the same way until someone joins.
+        require!(
+            matches!(
+                game_account.game_state,
+                GameState::InvitePending | GameState::WaitingForOpponent
+            ),
+            BattleShipErrorCode::InvalidGameState
+        );
+        require!(
+            ctx.accounts.player.key() == game_account.player_1,
+            BattleShipErrorCode::UnauthorizedPlayer
+        );
+
+        game_account.game_state = GameState::Cancelled;
+
+        emit!(GameCancelled {
+            game_pda: game_account.key(),
+            cancelled_by: Some(game_account.player_1),
+        });
+
         Ok(())
     }
 
@@ -73,27 +213,35 @@ pub mod confidential_battleship_game {
         ctx: Context<InitPlayerShipFleetLocCallback>,
         output: ComputationOutputs,
     ) -> Result<()> {
-        let bytes = if let ComputationOutputs::Bytes(bytes) = output {
-            bytes
-        } else {
-            return Err(BattleShipErrorCode::AbortedComputation.into());
+        let bytes = match output {
+            ComputationOutputs::Bytes(bytes) => bytes,
+            ComputationOutputs::Abort => {
+                let game_account = &mut ctx.accounts.game_account;
+                let game_pda = game_account.key();
+                game_account.abort_computation(game_pda);
+                // Without a fleet state there is nothing to place ships into, so the
+                // game is cancelled and any wagers can be refunded.
+                if game_account.game_state == GameState::PlacingShips {
+                    game_account.game_state = GameState::Cancelled;
+                    emit!(GameCancelled {
+                        game_pda,
+                        cancelled_by: None,
+                    });
+                }
+                return Ok(());
+            }
         };
 
-        let fleet_state_nonce: [u8; 16] = bytes[0..16].try_into().unwrap();
-
-        let encrypted_fleet_state: [[u8; 32]; 34] = bytes[16..]
-            .chunks_exact(32)
-            .map(|c| c.try_into().unwrap())
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap();
+        let (fleet_state_nonce, encrypted_fleet_state) = read_fleet_state(&bytes);
 
         let game_account = &mut ctx.accounts.game_account;
+        game_account.pending_computation = None;
         game_account.fleet_state_nonce = fleet_state_nonce;
         game_account.encrypted_fleet_state = encrypted_fleet_state;
 
         emit!(GameCreated {
             game_pda: game_account.key(),
+            game_id: game_account.game_id,
             player_1: game_account.player_1,
             player_2: game_account.player_2,
         });
@@ -101,6 +249,72 @@ pub mod confidential_battleship_game {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
+    pub fn open_game(
+        ctx: Context<NewGame>,
+        game_id: u64, // Client-chosen id, unique per creator.
+        player_1_arcium_pubkey: [u8; 32],
+        move_timeout: i64, // Seconds per placement or turn, 0 for the default.
+        wager: u64,        // Per-player stake in lamports or `wager_mint` units.
+        ranked: bool,      // Whether the result moves both players' ratings.
+        board_size: u8,    // Rows and columns of the board, 8 to 16.
+        fleet_preset: FleetPreset, // Ships each player places.
+    ) -> Result<()> {
+        init_game(
+            ctx.accounts,
+            &ctx.bumps,
+            MODE_OPEN,
+            game_id,
+            player_1_arcium_pubkey,
+            move_timeout,
+            wager,
+            ranked,
+            board_size,
+            fleet_preset,
+        )?;
+
+        // `player_2` stays unset until someone calls `join_game`.
+        let game_account = &mut ctx.accounts.game_account;
+        game_account.game_state = GameState::WaitingForOpponent;
+
+        emit!(GameOpened {
+            game_pda: game_account.key(),
+            game_id,
+            player_1: game_account.player_1,
+        });
+
+        Ok(())
+    }
+
+    pub fn join_game(
+        ctx: Context<JoinGame>,
+        computation_offset: u64,
+        player_2_arcium_pubkey: [u8; 32],
+        mxe_nonce: u128, // Nonce for the MXE to create the initial state.
+    ) -> Result<()> {
+        let game_account = &mut ctx.accounts.game_account;
+        require!(
+            game_account.game_state == GameState::WaitingForOpponent,
+            BattleShipErrorCode::InvalidGameState
+        );
+
+        let payer_key = ctx.accounts.payer.key();
+        require!(
+            payer_key != game_account.player_1,
+            BattleShipErrorCode::UnauthorizedPlayer
+        );
+
+        game_account.player_2 = payer_key;
+        game_account.player_2_arcium_pubkey = player_2_arcium_pubkey;
+
+        start_placement(
+            ctx.accounts,
+            ctx.bumps.player_profile,
+            computation_offset,
+            mxe_nonce,
+        )
+    }
+
     pub fn init_place_ships_comp_def(ctx: Context<InitPlaceShipsCompDef>) -> Result<()> {
         init_comp_def(ctx.accounts, true, None, None)?;
         Ok(())
@@ -110,42 +324,95 @@ pub mod confidential_battleship_game {
         ctx: Context<PlaceShipsIx>,
         computation_offset: u64,
         input_nonce: u128, // Nonce for the player's encrypted input.
-        encrypted_ship_locations: [u8; 32], // Client-encrypted `PlaceShipsInputs`.
+        encrypted_ships: [[[u8; 32]; 2]; MAX_FLEET_SHIPS], // Client-encrypted `ShipPlacement`s: bow, vertical.
+        encrypted_salt: [u8; 32], // Client-encrypted `u128` salt of the commitment.
+        commitment: [u8; 32],     // sha256 of the fleet's 19 `[row, col]` cells and the salt (LE).
     ) -> Result<()> {
-        let game_account = &ctx.accounts.game_account;
+        require!(
+            !ctx.accounts.config.paused,
+            BattleShipErrorCode::ProgramPaused
+        );
+
+        let game_account = &mut ctx.accounts.game_account;
+        let game_account_key = game_account.key();
         require!(
             game_account.game_state == GameState::PlacingShips,
             BattleShipErrorCode::InvalidGameState
         );
+        require!(
+            game_account.pending_computation.is_none(),
+            BattleShipErrorCode::ComputationPending
+        );
 
         let payer_key = ctx.accounts.payer.key();
         let (is_player_1, player_arcium_pubkey) = if payer_key == game_account.player_1 {
+            require!(
+                !game_account.player_1_placed,
+                BattleShipErrorCode::ShipsAlreadyPlaced
+            );
+            // A rejected fleet is placed again with a new commitment.
+            game_account.player_1_commitment = commitment;
             (true, game_account.player_1_arcium_pubkey)
         } else if payer_key == game_account.player_2 {
+            require!(
+                !game_account.player_2_placed,
+                BattleShipErrorCode::ShipsAlreadyPlaced
+            );
+            game_account.player_2_commitment = commitment;
             (false, game_account.player_2_arcium_pubkey)
         } else {
             return Err(BattleShipErrorCode::UnauthorizedPlayer.into());
         };
 
         // arguments for the `place_ships` encrypted instruction.
-        let args = vec![
-            // 1. Arguments for `Enc<Shared, PlaceShipsInputs>`
+        let mut args = vec![
+            // 1. Which fleet to overwrite, decided by the signer rather than the client,
+            //    the board it must fit on and the ships it consists of.
+            Argument::PlaintextBool(is_player_1),
+            Argument::PlaintextU8(game_account.board_size),
+            Argument::PlaintextU8(game_account.fleet_preset as u8),
+            // 2. Arguments for `Enc<Shared, PlaceShipsInputs>`
             Argument::ArcisPubkey(player_arcium_pubkey),
             Argument::PlaintextU128(input_nonce),
-            Argument::EncryptedU8(encrypted_ship_locations),
-            // 2. Arguments for `Enc<Mxe, PlayerShipFleet>`
-            Argument::PlaintextU128(u128::from_le_bytes(game_account.fleet_state_nonce)),
-            Argument::Account(game_account.key(), 8 + 148, 1088), // key, data offset, data size
         ];
+        for [bow, vertical] in encrypted_ships {
+            args.push(Argument::EncryptedU8(bow));
+            args.push(Argument::EncryptedBool(vertical));
+        }
+        args.push(Argument::EncryptedU128(encrypted_salt));
+        // 3. Arguments for `Enc<Mxe, PlayerShipFleet>`
+        args.push(Argument::PlaintextU128(u128::from_le_bytes(
+            game_account.fleet_state_nonce,
+        )));
+        args.push(Argument::Account(
+            game_account_key,
+            FLEET_STATE_OFFSET,
+            FLEET_STATE_SIZE,
+        )); // key, data offset, data size
+
+        game_account.pending_computation = Some(computation_offset);
+        let computation_account_key = ctx.accounts.computation_account.key();
+        let config_key = ctx.accounts.config.key();
 
         queue_computation(
             ctx.accounts,
             computation_offset,
             args,
-            vec![CallbackAccount {
-                pubkey: game_account.key(),
-                is_writable: true,
-            }],
+            vec![
+                CallbackAccount {
+                    pubkey: game_account_key,
+                    is_writable: true,
+                },
+                CallbackAccount {
+                    pubkey: computation_account_key,
+                    is_writable: false,
+                },
+                // Pause time, so the battle's first deadline does not count it.
+                CallbackAccount {
+                    pubkey: config_key,
+                    is_writable: false,
+                },
+            ],
             None,
         )?;
 
@@ -157,28 +424,63 @@ pub mod confidential_battleship_game {
         ctx: Context<PlaceShipsIxCallback>,
         output: ComputationOutputs,
     ) -> Result<()> {
-        let bytes = if let ComputationOutputs::Bytes(bytes) = output {
-            bytes
-        } else {
-            return Err(BattleShipErrorCode::AbortedComputation.into());
+        let bytes = match output {
+            ComputationOutputs::Bytes(bytes) => bytes,
+            ComputationOutputs::Abort => {
+                let game_account = &mut ctx.accounts.game_account;
+                let game_pda = game_account.key();
+                game_account.abort_computation(game_pda);
+                // The mover did move, the clock must not run out on them while they retry.
+                game_account.reset_move_deadline(&ctx.accounts.config)?;
+                return Ok(());
+            }
         };
 
-        let new_fleet_state_nonce: [u8; 16] = bytes[0..16].try_into().unwrap();
-
-        let new_encrypted_fleet_state: [[u8; 32]; 34] = bytes[16..]
-            .chunks_exact(32)
-            .map(|c| c.try_into().unwrap())
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap();
+        let (new_fleet_state_nonce, new_encrypted_fleet_state) = read_fleet_state(&bytes);
+        let is_player_1 = bytes[FLEET_STATE_OUTPUT_LEN] == 1;
+        let is_valid = bytes[FLEET_STATE_OUTPUT_LEN + 1] == 1;
 
         let game_account = &mut ctx.accounts.game_account;
+        game_account.pending_computation = None;
         game_account.fleet_state_nonce = new_fleet_state_nonce;
         game_account.encrypted_fleet_state = new_encrypted_fleet_state;
 
+        // The game ended (e.g. by resignation) while the computation was in flight.
+        if game_account.game_state == GameState::Finished {
+            return Ok(());
+        }
+
+        // The circuit left the stored fleet untouched, the player may try again.
+        if !is_valid {
+            emit!(ShipsRejected {
+                game_pda: game_account.key(),
+                is_player_1,
+            });
+            return Ok(());
+        }
+
+        if is_player_1 {
+            game_account.player_1_placed = true;
+        } else {
+            game_account.player_2_placed = true;
+        }
+
         emit!(ShipsPlaced {
-            game_pda: game_account.key()
+            game_pda: game_account.key(),
+            is_player_1,
         });
+
+        // Both fleets are committed, the battle can begin.
+        if game_account.player_1_placed && game_account.player_2_placed {
+            game_account.game_state = GameState::Player1Turn;
+            game_account.reset_move_deadline(&ctx.accounts.config)?;
+
+            emit!(BattleStarted {
+                game_pda: game_account.key(),
+                first_turn: game_account.player_1,
+            });
+        }
+
         Ok(())
     }
 
@@ -190,42 +492,90 @@ pub mod confidential_battleship_game {
     pub fn take_turn_ix(
         ctx: Context<TakeTurnIx>,
         computation_offset: u64,
-        input_nonce: u128,         // Nonce for the player's encrypted guess.
-        encrypted_guess: [u8; 32], // Client-encrypted `TakeTurnInputs`.
+        row: u8,
+        col: u8,
     ) -> Result<()> {
+        require!(
+            !ctx.accounts.config.paused,
+            BattleShipErrorCode::ProgramPaused
+        );
+
         let game_account = &mut ctx.accounts.game_account;
         let payer_key = ctx.accounts.payer.key();
         let game_account_key = game_account.key();
+        require!(
+            game_account.pending_computation.is_none(),
+            BattleShipErrorCode::ComputationPending
+        );
 
-        let (is_player_1, player_arcium_pubkey) = match game_account.game_state {
-            GameState::Player1Turn if payer_key == game_account.player_1 => {
-                (true, game_account.player_1_arcium_pubkey)
-            }
-            GameState::Player2Turn if payer_key == game_account.player_2 => {
-                (false, game_account.player_2_arcium_pubkey)
-            }
+        let is_player_1 = match game_account.game_state {
+            GameState::Player1Turn if payer_key == game_account.player_1 => true,
+            GameState::Player2Turn if payer_key == game_account.player_2 => false,
             _ => return Err(BattleShipErrorCode::InvalidTurn.into()),
         };
 
+        // Reject wasted shots before any MPC fee is spent.
+        require!(
+            row < game_account.board_size && col < game_account.board_size,
+            BattleShipErrorCode::InvalidCoordinates
+        );
+        let shots = if is_player_1 {
+            game_account.player_1_shots
+        } else {
+            game_account.player_2_shots
+        };
+        let (word, bit) = cell_bit(game_account.board_size, row, col);
+        require!(
+            shots[word] & bit == 0,
+            BattleShipErrorCode::CellAlreadyFired
+        );
+        game_account.last_guess = [row, col];
+
         // Arguments for the `take_turn` encrypted instruction.
         let args = vec![
-            // 1. Arguments for `Enc<Shared, TakeTurnInputs>`
-            Argument::ArcisPubkey(player_arcium_pubkey),
-            Argument::PlaintextU128(input_nonce),
-            Argument::EncryptedU8(encrypted_guess), // this needs to be encrypted u32
+            // 1. Plaintext `is_player_1`, `row`, `col`
+            Argument::PlaintextBool(is_player_1),
+            Argument::PlaintextU8(row),
+            Argument::PlaintextU8(col),
             // 2. Arguments for `Enc<Mxe, PlayerShipFleet>`
             Argument::PlaintextU128(u128::from_le_bytes(game_account.fleet_state_nonce)),
-            Argument::Account(game_account.key(), 8 + 148, 1088), // key, data offset, data size
+            Argument::Account(game_account_key, FLEET_STATE_OFFSET, FLEET_STATE_SIZE), // key, data offset, data size
         ];
 
+        game_account.pending_computation = Some(computation_offset);
+        let computation_account_key = ctx.accounts.computation_account.key();
+        let player_1_profile_key = ctx.accounts.player_1_profile.key();
+        let player_2_profile_key = ctx.accounts.player_2_profile.key();
+        let config_key = ctx.accounts.config.key();
+
         queue_computation(
             ctx.accounts,
             computation_offset,
             args,
-            vec![CallbackAccount {
-                pubkey: game_account_key,
-                is_writable: true,
-            }],
+            vec![
+                CallbackAccount {
+                    pubkey: game_account_key,
+                    is_writable: true,
+                },
+                CallbackAccount {
+                    pubkey: computation_account_key,
+                    is_writable: false,
+                },
+                // Shot statistics, and the result if this turn ends the game.
+                CallbackAccount {
+                    pubkey: player_1_profile_key,
+                    is_writable: true,
+                },
+                CallbackAccount {
+                    pubkey: player_2_profile_key,
+                    is_writable: true,
+                },
+                // Pause time, so the next deadline does not count it.
+                CallbackAccount {
+                    pubkey: config_key,
+                    is_writable: false,
+                },
+            ],
             None,
         )?;
 
@@ -237,44 +587,107 @@ pub mod confidential_battleship_game {
         ctx: Context<TakeTurnCallback>,
         output: ComputationOutputs,
     ) -> Result<()> {
-        let bytes = if let ComputationOutputs::Bytes(bytes) = output {
-            bytes
-        } else {
-            return Err(BattleShipErrorCode::AbortedComputation.into());
+        let bytes = match output {
+            ComputationOutputs::Bytes(bytes) => bytes,
+            ComputationOutputs::Abort => {
+                let game_account = &mut ctx.accounts.game_account;
+                let game_pda = game_account.key();
+                game_account.abort_computation(game_pda);
+                // The mover did move, the clock must not run out on them while they retry.
+                game_account.reset_move_deadline(&ctx.accounts.config)?;
+                return Ok(());
+            }
         };
 
-        let was_hit = bytes[0] == 1;
+        let (new_fleet_state_nonce, new_encrypted_fleet_state) = read_fleet_state(&bytes);
+        let was_hit = bytes[FLEET_STATE_OUTPUT_LEN] == 1;
+        // Slot of the sunk ship, the circuit reveals 255 when the shot sank nothing.
+        let sunk_slot = Some(bytes[FLEET_STATE_OUTPUT_LEN + 1])
+            .filter(|&slot| (slot as usize) < MAX_FLEET_SHIPS);
+
         let game_account = &mut ctx.accounts.game_account;
+        game_account.pending_computation = None;
+        // Ship health was updated inside the circuit.
+        game_account.fleet_state_nonce = new_fleet_state_nonce;
+        game_account.encrypted_fleet_state = new_encrypted_fleet_state;
+
+        // The game ended (e.g. by resignation) while the computation was in flight.
+        if game_account.game_state == GameState::Finished {
+            return Ok(());
+        }
 
         // Check the turn to know whose ship count to decrement.
         let was_player_1_turn = game_account.game_state == GameState::Player1Turn;
 
+        let [row, col] = game_account.last_guess;
+        let (word, bit) = cell_bit(game_account.board_size, row, col);
+        if was_player_1_turn {
+            game_account.player_1_shots[word] |= bit;
+            if was_hit {
+                game_account.player_1_hits[word] |= bit;
+            }
+            if let Some(slot) = sunk_slot {
+                game_account.player_2_sunk_ships |= 1 << slot;
+            }
+        } else {
+            game_account.player_2_shots[word] |= bit;
+            if was_hit {
+                game_account.player_2_hits[word] |= bit;
+            }
+            if let Some(slot) = sunk_slot {
+                game_account.player_1_sunk_ships |= 1 << slot;
+            }
+        }
+
+        game_account.turn_count += 1;
+
+        let shooter_profile = if was_player_1_turn {
+            &mut ctx.accounts.player_1_profile
+        } else {
+            &mut ctx.accounts.player_2_profile
+        };
+        shooter_profile.record_shot(was_hit);
+
         if was_hit {
             if was_player_1_turn {
                 game_account.player_2_ships_left -= 1;
-                if game_account.player_2_ships_left == 0 {
-                    game_account.game_state = GameState::Finished;
-                }
             } else {
                 game_account.player_1_ships_left -= 1;
-                if game_account.player_1_ships_left == 0 {
-                    game_account.game_state = GameState::Finished;
-                }
             }
         }
 
-        // Advance the turn if the game is not over.
-        if game_account.game_state != GameState::Finished {
+        if game_account.player_1_ships_left == 0 || game_account.player_2_ships_left == 0 {
+            // The shooter sank the last enemy ship.
+            let winner = if was_player_1_turn {
+                game_account.player_1
+            } else {
+                game_account.player_2
+            };
+            let game_pda = game_account.key();
+            game_account.finish(
+                game_pda,
+                winner,
+                FinishReason::AllShipsSunk,
+                &mut ctx.accounts.player_1_profile,
+                &mut ctx.accounts.player_2_profile,
+            )?;
+        } else {
+            // Advance the turn, the game is not over.
             game_account.game_state = if was_player_1_turn {
                 GameState::Player2Turn
             } else {
                 GameState::Player1Turn
             };
+            game_account.reset_move_deadline(&ctx.accounts.config)?;
         }
 
         emit!(TurnResult {
             game_pda: game_account.key(),
+            row,
+            col,
             was_hit,
+            sunk_ship: sunk_slot
+                .and_then(|slot| game_account.fleet_preset.ship_kinds()[slot as usize]),
             ships_left_player_1: game_account.player_1_ships_left,
             ships_left_player_2: game_account.player_2_ships_left,
             new_game_state: game_account.game_state,
@@ -282,90 +695,1080 @@ pub mod confidential_battleship_game {
 
         Ok(())
     }
-}
 
-#[queue_computation_accounts("init_player_ship_fleet_location", payer)]
-#[derive(Accounts)]
-#[instruction(computation_offset: u64)]
-pub struct NewGame<'info> {
-    // the game pda creator, player1
-    #[account(mut)]
-    pub payer: Signer<'info>,
+    pub fn init_reveal_fleets_comp_def(ctx: Context<InitRevealFleetsCompDef>) -> Result<()> {
+        init_comp_def(ctx.accounts, true, None, None)?;
+        Ok(())
+    }
 
-    #[account(
-        address = derive_mxe_pda!()
-    )]
-    pub mxe_account: Account<'info, PersistentMXEAccount>,
-    #[account(
-        mut,
-        address = derive_mempool_pda!()
-    )]
-    /// CHECK: mempool_account, checked by the arcium program.
-    pub mempool_account: UncheckedAccount<'info>,
-    #[account(
-        mut,
-        address = derive_execpool_pda!()
-    )]
-    /// CHECK: executing_pool, checked by the arcium program.
-    pub executing_pool: UncheckedAccount<'info>,
-    #[account(
-        mut,
-        address = derive_comp_pda!(computation_offset)
-    )]
-    /// CHECK: computation_account, checked by the arcium program.
-    pub computation_account: UncheckedAccount<'info>,
-    #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_INIT_PLAYER_SHIPS) // for mxe cluster to access mxe bytecode and metadata
-    )]
-    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
-    #[account(
-        mut,
-        address = derive_cluster_pda!(mxe_account)
-    )]
-    pub cluster_account: Account<'info, Cluster>,
-    #[account(
-        mut,
-        address = ARCIUM_STAKING_POOL_ACCOUNT_ADDRESS,
-    )]
-    pub pool_account: Account<'info, StakingPoolAccount>,
-    #[account(
-        address = ARCIUM_CLOCK_ACCOUNT_ADDRESS
-    )]
-    pub clock_account: Account<'info, ClockAccount>,
-    pub system_program: Program<'info, System>,
-    pub arcium_program: Program<'info, Arcium>,
-    // game account
-    #[account(
-        init,
-        payer = payer,
-        space = 8 + GameData::INIT_SPACE,
-        seeds = [b"game_data_account"],
-        bump,
-    )]
-    pub game_account: Account<'info, GameData>,
-}
+    /// Decrypts both fleets as placed once the game is over, anyone may pay for it.
+    pub fn reveal_fleets_ix(ctx: Context<RevealFleetsIx>, computation_offset: u64) -> Result<()> {
+        let game_account = &mut ctx.accounts.game_account;
+        let game_account_key = game_account.key();
+        require!(
+            game_account.game_state == GameState::Finished,
+            BattleShipErrorCode::InvalidGameState
+        );
+        require!(
+            game_account.pending_computation.is_none(),
+            BattleShipErrorCode::ComputationPending
+        );
+        require!(
+            !game_account.fleets_revealed,
+            BattleShipErrorCode::FleetsAlreadyRevealed
+        );
 
-#[callback_accounts("init_player_ship_fleet_location", payer)]
-#[derive(Accounts)]
-pub struct InitPlayerShipFleetLocCallback<'info> {
-    #[account(mut)]
-    pub payer: Signer<'info>,
-    pub arcium_program: Program<'info, Arcium>,
-    #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_INIT_PLAYER_SHIPS)
-    )]
-    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
-    #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
-    /// CHECK: instructions_sysvar, checked by the account constraint
-    pub instructions_sysvar: AccountInfo<'info>,
+        // Arguments for `Enc<Mxe, PlayerShipFleet>`, the only input of `reveal_fleets`.
+        let args = vec![
+            Argument::PlaintextU128(u128::from_le_bytes(game_account.fleet_state_nonce)),
+            Argument::Account(game_account_key, FLEET_STATE_OFFSET, FLEET_STATE_SIZE), // key, data offset, data size
+        ];
+
+        game_account.pending_computation = Some(computation_offset);
+        let computation_account_key = ctx.accounts.computation_account.key();
+
+        queue_computation(
+            ctx.accounts,
+            computation_offset,
+            args,
+            vec![
+                CallbackAccount {
+                    pubkey: game_account_key,
+                    is_writable: true,
+                },
+                CallbackAccount {
+                    pubkey: computation_account_key,
+                    is_writable: false,
+                },
+            ],
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    /// Publishes both fleets and their salts. The circuits never see the commitments,
+    /// so this is where they are checked: the program hashes what the MXE revealed and
+    /// compares it to what each player committed to before the first shot.
+    #[arcium_callback(encrypted_ix = "reveal_fleets")]
+    pub fn reveal_fleets_callback(
+        ctx: Context<RevealFleetsCallback>,
+        output: ComputationOutputs,
+    ) -> Result<()> {
+        let bytes = match output {
+            ComputationOutputs::Bytes(bytes) => bytes,
+            ComputationOutputs::Abort => {
+                let game_pda = ctx.accounts.game_account.key();
+                ctx.accounts.game_account.abort_computation(game_pda);
+                return Ok(());
+            }
+        };
+
+        // Revealed `[row, col]` cells of player 1, then those of player 2, then both salts.
+        const CELLS_LEN: usize = 2 * MAX_FLEET_CELLS * 2;
+        let mut fleets = [[[0u8; 2]; MAX_FLEET_CELLS]; 2];
+        for (i, cell) in bytes[..CELLS_LEN].chunks_exact(2).enumerate() {
+            fleets[i / MAX_FLEET_CELLS][i % MAX_FLEET_CELLS] = [cell[0], cell[1]];
+        }
+        let [player_1_fleet, player_2_fleet] = fleets;
+        let salts = &bytes[CELLS_LEN..];
+        let player_1_salt: [u8; 16] = salts[..16].try_into().unwrap();
+        let player_2_salt: [u8; 16] = salts[16..32].try_into().unwrap();
+
+        let game_account = &mut ctx.accounts.game_account;
+        game_account.pending_computation = None;
+        game_account.fleets_revealed = true;
+
+        emit!(FleetsRevealed {
+            game_pda: game_account.key(),
+            player_1_fleet,
+            player_2_fleet,
+            player_1_commitment_valid: fleet_commitment(&player_1_fleet, &player_1_salt)
+                == game_account.player_1_commitment,
+            player_2_commitment_valid: fleet_commitment(&player_2_fleet, &player_2_salt)
+                == game_account.player_2_commitment,
+        });
+
+        Ok(())
+    }
+
+    pub fn resign(ctx: Context<Resign>) -> Result<()> {
+        let game_account = &mut ctx.accounts.game_account;
+        // Before placement there is no opponent to hand the win to.
+        require!(
+            matches!(
+                game_account.game_state,
+                GameState::PlacingShips | GameState::Player1Turn | GameState::Player2Turn
+            ),
+            BattleShipErrorCode::InvalidGameState
+        );
+
+        let player_key = ctx.accounts.player.key();
+        let winner = if player_key == game_account.player_1 {
+            game_account.player_2
+        } else if player_key == game_account.player_2 {
+            game_account.player_1
+        } else {
+            return Err(BattleShipErrorCode::UnauthorizedPlayer.into());
+        };
+
+        let game_pda = game_account.key();
+        game_account.finish(
+            game_pda,
+            winner,
+            FinishReason::Forfeit,
+            &mut ctx.accounts.player_1_profile,
+            &mut ctx.accounts.player_2_profile,
+        )
+    }
+
+    pub fn claim_timeout(ctx: Context<ClaimTimeout>) -> Result<()> {
+        let game_account = &mut ctx.accounts.game_account;
+        // A queued move is not a stalled one, wait for its callback.
+        require!(
+            game_account.pending_computation.is_none(),
+            BattleShipErrorCode::ComputationPending
+        );
+        require!(
+            game_account.is_past_deadline(&ctx.accounts.config, Clock::get()?.unix_timestamp),
+            BattleShipErrorCode::DeadlineNotReached
+        );
+
+        let player_key = ctx.accounts.player.key();
+        let is_player_1 = if player_key == game_account.player_1 {
+            true
+        } else if player_key == game_account.player_2 {
+            false
+        } else {
+            return Err(BattleShipErrorCode::UnauthorizedPlayer.into());
+        };
+
+        // Neither fleet was placed in time, nobody has earned the win.
+        if game_account.game_state == GameState::PlacingShips
+            && !game_account.player_1_placed
+            && !game_account.player_2_placed
+        {
+            game_account.game_state = GameState::Cancelled;
+
+            emit!(GameCancelled {
+                game_pda: game_account.key(),
+                cancelled_by: Some(player_key),
+            });
+
+            return Ok(());
+        }
+
+        // Only the player the game is waiting on can lose by timeout.
+        let is_waiting = match game_account.game_state {
+            GameState::PlacingShips if is_player_1 => {
+                game_account.player_1_placed && !game_account.player_2_placed
+            }
+            GameState::PlacingShips => {
+                game_account.player_2_placed && !game_account.player_1_placed
+            }
+            GameState::Player1Turn => !is_player_1,
+            GameState::Player2Turn => is_player_1,
+            _ => return Err(BattleShipErrorCode::InvalidGameState.into()),
+        };
+        require!(is_waiting, BattleShipErrorCode::NotWaitingOnOpponent);
+
+        let game_pda = game_account.key();
+        game_account.finish(
+            game_pda,
+            player_key,
+            FinishReason::Timeout,
+            &mut ctx.accounts.player_1_profile,
+            &mut ctx.accounts.player_2_profile,
+        )
+    }
+
+    pub fn close_game(ctx: Context<CloseGame>) -> Result<()> {
+        let game_account = &ctx.accounts.game_account;
+        require!(
+            matches!(
+                game_account.game_state,
+                GameState::Finished | GameState::Cancelled
+            ),
+            BattleShipErrorCode::InvalidGameState
+        );
+        // A callback still in flight would have nowhere to write.
+        require!(
+            game_account.pending_computation.is_none(),
+            BattleShipErrorCode::ComputationPending
+        );
+        require!(
+            game_account.wager == 0 || game_account.wager_settled,
+            BattleShipErrorCode::WagerUnsettled
+        );
+
+        let player_key = ctx.accounts.player.key();
+        require!(
+            player_key == game_account.player_1 || player_key == game_account.player_2,
+            BattleShipErrorCode::UnauthorizedPlayer
+        );
+
+        if let Some(token_wager) = token_wager_accounts(
+            game_account.wager_mint,
+            ctx.accounts.wager_mint.as_deref(),
+            ctx.accounts.player_1_token_account.as_deref(),
+            ctx.accounts.wager_vault.as_deref(),
+            ctx.accounts.token_program.as_ref(),
+        )? {
+            let vault = token_wager.vault.to_account_info();
+            let token_program = token_wager.token_program.to_account_info();
+
+            // Tokens sent to the vault after settlement go to player 1 along with its rent,
+            // nobody could sign for them once the game account is gone.
+            let stray = token_wager.vault.amount;
+            if stray > 0 {
+                pay_out_wager(
+                    game_account,
+                    &ctx.accounts.escrow,
+                    &ctx.accounts.player_1.to_account_info(),
+                    Some(token_wager),
+                    stray,
+                )?;
+            }
+
+            let game_id = game_account.game_id.to_le_bytes();
+            let signer_seeds: &[&[&[u8]]] = &[&[
+                b"game_data_account",
+                game_account.player_1.as_ref(),
+                &game_id,
+                &[game_account.bump],
+            ]];
+            close_account(CpiContext::new_with_signer(
+                token_program,
+                CloseAccount {
+                    account: vault,
+                    destination: ctx.accounts.player_1.to_account_info(),
+                    authority: game_account.to_account_info(),
+                },
+                signer_seeds,
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn claim_winnings(ctx: Context<ClaimWinnings>) -> Result<()> {
+        let game_account = &mut ctx.accounts.game_account;
+        require!(
+            game_account.game_state == GameState::Finished,
+            BattleShipErrorCode::InvalidGameState
+        );
+        require!(game_account.wager > 0, BattleShipErrorCode::NoWager);
+        require!(
+            !game_account.wager_settled,
+            BattleShipErrorCode::WagerSettled
+        );
+        require!(
+            game_account.winner == Some(ctx.accounts.winner.key()),
+            BattleShipErrorCode::NotWinner
+        );
+
+        // A finished game went through placement, so both players have deposited.
+        let pot = game_account.wager * 2;
+        let fee = game_account.pot_fee();
+        game_account.wager_settled = true;
+
+        let winner_wager = token_wager_accounts(
+            game_account.wager_mint,
+            ctx.accounts.wager_mint.as_deref(),
+            ctx.accounts.winner_token_account.as_deref(),
+            ctx.accounts.wager_vault.as_deref(),
+            ctx.accounts.token_program.as_ref(),
+        )?;
+        pay_out_wager(
+            game_account,
+            &ctx.accounts.escrow,
+            &ctx.accounts.winner.to_account_info(),
+            winner_wager,
+            pot - fee,
+        )?;
+
+        if fee > 0 {
+            let treasury_wager = token_wager_accounts(
+                game_account.wager_mint,
+                ctx.accounts.wager_mint.as_deref(),
+                ctx.accounts.treasury_token_account.as_deref(),
+                ctx.accounts.wager_vault.as_deref(),
+                ctx.accounts.token_program.as_ref(),
+            )?;
+            pay_out_wager(
+                game_account,
+                &ctx.accounts.escrow,
+                &ctx.accounts.treasury.to_account_info(),
+                treasury_wager,
+                fee,
+            )?;
+        }
+
+        emit!(WinningsClaimed {
+            game_pda: game_account.key(),
+            winner: ctx.accounts.winner.key(),
+            amount: pot - fee,
+            fee,
+        });
+
+        Ok(())
+    }
+
+    pub fn refund_wager(ctx: Context<RefundWager>) -> Result<()> {
+        let game_account = &mut ctx.accounts.game_account;
+        require!(
+            game_account.game_state == GameState::Cancelled,
+            BattleShipErrorCode::InvalidGameState
+        );
+        require!(game_account.wager > 0, BattleShipErrorCode::NoWager);
+        require!(
+            !game_account.wager_settled,
+            BattleShipErrorCode::WagerSettled
+        );
+
+        let wager = game_account.wager;
+        game_account.wager_settled = true;
+
+        let player_1_wager = token_wager_accounts(
+            game_account.wager_mint,
+            ctx.accounts.wager_mint.as_deref(),
+            ctx.accounts.player_1_token_account.as_deref(),
+            ctx.accounts.wager_vault.as_deref(),
+            ctx.accounts.token_program.as_ref(),
+        )?;
+        pay_out_wager(
+            game_account,
+            &ctx.accounts.escrow,
+            &ctx.accounts.player_1.to_account_info(),
+            player_1_wager,
+            wager,
+        )?;
+
+        if game_account.player_2_deposited {
+            let player_2 = ctx
+                .accounts
+                .player_2
+                .as_ref()
+                .ok_or(BattleShipErrorCode::MissingPlayer2)?;
+            let player_2_wager = token_wager_accounts(
+                game_account.wager_mint,
+                ctx.accounts.wager_mint.as_deref(),
+                ctx.accounts.player_2_token_account.as_deref(),
+                ctx.accounts.wager_vault.as_deref(),
+                ctx.accounts.token_program.as_ref(),
+            )?;
+            pay_out_wager(
+                game_account,
+                &ctx.accounts.escrow,
+                &player_2.to_account_info(),
+                player_2_wager,
+                wager,
+            )?;
+        }
+
+        emit!(WagerRefunded {
+            game_pda: game_account.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Creates the program config, the signer becomes its admin.
+    pub fn init_config(
+        ctx: Context<InitConfig>,
+        fee_bps: u16,
+        default_move_timeout: i64,
+        allowed_modes: u8, // `MODE_*` bits
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.bump = ctx.bumps.config;
+        config.authority = ctx.accounts.authority.key();
+        config.set(
+            &ctx.accounts.treasury,
+            fee_bps,
+            default_move_timeout,
+            allowed_modes,
+            false,
+        )
+    }
+
+    pub fn update_config(
+        ctx: Context<SetConfig>,
+        fee_bps: u16,
+        default_move_timeout: i64,
+        allowed_modes: u8, // `MODE_*` bits
+        paused: bool,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require!(
+            ctx.accounts.authority.key() == config.authority,
+            BattleShipErrorCode::UnauthorizedAdmin
+        );
+
+        config.set(
+            &ctx.accounts.treasury,
+            fee_bps,
+            default_move_timeout,
+            allowed_modes,
+            paused,
+        )
+    }
+
+    /// Offers the config to `new_authority`, who takes over with `accept_authority`.
+    pub fn propose_authority(ctx: Context<UpdateConfig>, new_authority: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require!(
+            ctx.accounts.authority.key() == config.authority,
+            BattleShipErrorCode::UnauthorizedAdmin
+        );
+
+        // A mistyped key can be replaced by proposing again.
+        config.pending_authority = Some(new_authority);
+
+        emit!(AuthorityProposed {
+            authority: config.authority,
+            pending_authority: new_authority,
+        });
+
+        Ok(())
+    }
+
+    /// Completes a handover started by `propose_authority`.
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let new_authority = ctx.accounts.new_authority.key();
+        require!(
+            config.pending_authority == Some(new_authority),
+            BattleShipErrorCode::UnauthorizedAdmin
+        );
+
+        let previous_authority = config.authority;
+        config.authority = new_authority;
+        config.pending_authority = None;
+
+        emit!(AuthorityTransferred {
+            previous_authority,
+            authority: new_authority,
+        });
+
+        Ok(())
+    }
+
+    /// Stops or resumes new games, placements and turns without touching other settings.
+    pub fn set_paused(ctx: Context<UpdateConfig>, paused: bool) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require!(
+            ctx.accounts.authority.key() == config.authority,
+            BattleShipErrorCode::UnauthorizedAdmin
+        );
+
+        config.update_pause(paused)
+    }
+}
+
+/// Sets up the game `payer` creates as `player_1` and takes their stake, shared by
+/// `new_game` and `open_game`, which then fill in the opponent side.
+#[allow(clippy::too_many_arguments)]
+fn init_game(
+    accounts: &mut NewGame,
+    bumps: &NewGameBumps,
+    mode: u8,
+    game_id: u64,
+    player_1_arcium_pubkey: [u8; 32],
+    move_timeout: i64,
+    wager: u64,
+    ranked: bool,
+    board_size: u8,
+    fleet_preset: FleetPreset,
+) -> Result<()> {
+    let config = &accounts.config;
+    let move_timeout = config.check_new_game(mode, move_timeout, wager, ranked)?;
+    require!(
+        (MIN_BOARD_SIZE..=MAX_BOARD_SIZE).contains(&board_size),
+        BattleShipErrorCode::InvalidBoardSize
+    );
+
+    let game_account = &mut accounts.game_account;
+    game_account.game_id = game_id;
+    game_account.player_1 = accounts.payer.key();
+    game_account.player_1_arcium_pubkey = player_1_arcium_pubkey;
+    game_account.player_1_ships_left = fleet_preset.cells();
+    game_account.player_2_ships_left = fleet_preset.cells();
+    game_account.bump = bumps.game_account;
+    game_account.move_timeout = move_timeout;
+    game_account.wager = wager;
+    game_account.wager_mint = accounts.wager_mint.as_ref().map(|mint| mint.key());
+    // Later fee changes do not apply to games already created.
+    game_account.fee_bps = config.fee_bps;
+    game_account.ranked = ranked;
+    game_account.board_size = board_size;
+    game_account.fleet_preset = fleet_preset;
+
+    accounts
+        .player_profile
+        .init(accounts.payer.key(), bumps.player_profile);
+    accounts.escrow.bump = bumps.escrow;
+
+    let token_wager = token_wager_accounts(
+        game_account.wager_mint,
+        accounts.wager_mint.as_deref(),
+        accounts.payer_token_account.as_deref(),
+        accounts.wager_vault.as_deref(),
+        accounts.token_program.as_ref(),
+    )?;
+    deposit_wager(
+        &accounts.payer,
+        &accounts.escrow,
+        &accounts.system_program,
+        token_wager,
+        wager,
+    )
+}
+
+/// Opens placement once both players are known and queues the empty, encrypted fleet state.
+fn start_placement(
+    accounts: &mut JoinGame,
+    player_profile_bump: u8,
+    computation_offset: u64,
+    mxe_nonce: u128,
+) -> Result<()> {
+    require!(!accounts.config.paused, BattleShipErrorCode::ProgramPaused);
+
+    accounts
+        .player_profile
+        .init(accounts.payer.key(), player_profile_bump);
+
+    // The opponent matches `player_1`'s stake.
+    let token_wager = token_wager_accounts(
+        accounts.game_account.wager_mint,
+        accounts.wager_mint.as_deref(),
+        accounts.payer_token_account.as_deref(),
+        accounts.wager_vault.as_deref(),
+        accounts.token_program.as_ref(),
+    )?;
+    deposit_wager(
+        &accounts.payer,
+        &accounts.escrow,
+        &accounts.system_program,
+        token_wager,
+        accounts.game_account.wager,
+    )?;
+
+    let game_account = &mut accounts.game_account;
+    game_account.player_2_deposited = true;
+    game_account.game_state = GameState::PlacingShips;
+    game_account.pending_computation = Some(computation_offset);
+    // Placement opens now.
+    game_account.reset_move_deadline(&accounts.config)?;
+
+    let game_account_key = game_account.key();
+    let computation_account_key = accounts.computation_account.key();
+
+    let args = vec![
+        Argument::PlaintextU8(accounts.game_account.fleet_preset as u8),
+        Argument::PlaintextU128(mxe_nonce),
+    ];
+
+    queue_computation(
+        accounts,
+        computation_offset,
+        args,
+        vec![
+            CallbackAccount {
+                pubkey: game_account_key,
+                is_writable: true,
+            },
+            CallbackAccount {
+                pubkey: computation_account_key,
+                is_writable: false,
+            },
+        ],
+        None,
+    )?;
+    Ok(())
+}
+
+/// Token accounts an SPL wager moves through.
+struct TokenWager<'a, 'info> {
+    mint: &'a Account<'info, Mint>,
+    token_account: &'a Account<'info, TokenAccount>, // the player's side of the transfer
+    vault: &'a Account<'info, TokenAccount>,
+    token_program: &'a Program<'info, Token>,
+}
+
+/// Collects the token accounts of a game wagering `wager_mint`, `None` for lamport wagers.
+fn token_wager_accounts<'a, 'info>(
+    wager_mint: Option<Pubkey>,
+    mint: Option<&'a Account<'info, Mint>>,
+    token_account: Option<&'a Account<'info, TokenAccount>>,
+    vault: Option<&'a Account<'info, TokenAccount>>,
+    token_program: Option<&'a Program<'info, Token>>,
+) -> Result<Option<TokenWager<'a, 'info>>> {
+    let Some(wager_mint) = wager_mint else {
+        return Ok(None);
+    };
+    let (Some(mint), Some(token_account), Some(vault), Some(token_program)) =
+        (mint, token_account, vault, token_program)
+    else {
+        return Err(BattleShipErrorCode::MissingTokenAccounts.into());
+    };
+    require_keys_eq!(mint.key(), wager_mint, BattleShipErrorCode::WrongWagerMint);
+
+    Ok(Some(TokenWager {
+        mint,
+        token_account,
+        vault,
+        token_program,
+    }))
+}
+
+/// Moves `amount` from `from` into the game's escrow, or its token vault for SPL wagers.
+fn deposit_wager<'info>(
+    from: &Signer<'info>,
+    escrow: &Account<'info, WagerEscrow>,
+    system_program: &Program<'info, System>,
+    token_wager: Option<TokenWager<'_, 'info>>,
+    amount: u64,
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+
+    if let Some(token_wager) = token_wager {
+        return transfer_checked(
+            CpiContext::new(
+                token_wager.token_program.to_account_info(),
+                TransferChecked {
+                    from: token_wager.token_account.to_account_info(),
+                    mint: token_wager.mint.to_account_info(),
+                    to: token_wager.vault.to_account_info(),
+                    authority: from.to_account_info(),
+                },
+            ),
+            amount,
+            token_wager.mint.decimals,
+        );
+    }
+
+    transfer(
+        CpiContext::new(
+            system_program.to_account_info(),
+            Transfer {
+                from: from.to_account_info(),
+                to: escrow.to_account_info(),
+            },
+        ),
+        amount,
+    )
+}
+
+/// Moves `amount` out of the game's escrow to `recipient`, or out of its token vault for SPL wagers.
+fn pay_out_wager<'info>(
+    game_account: &Account<'info, GameData>,
+    escrow: &Account<'info, WagerEscrow>,
+    recipient: &AccountInfo<'info>,
+    token_wager: Option<TokenWager<'_, 'info>>,
+    amount: u64,
+) -> Result<()> {
+    let Some(token_wager) = token_wager else {
+        escrow.sub_lamports(amount)?;
+        recipient.add_lamports(amount)?;
+        return Ok(());
+    };
+
+    // The vault belongs to the game PDA.
+    let game_id = game_account.game_id.to_le_bytes();
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        b"game_data_account",
+        game_account.player_1.as_ref(),
+        &game_id,
+        &[game_account.bump],
+    ]];
+    transfer_checked(
+        CpiContext::new_with_signer(
+            token_wager.token_program.to_account_info(),
+            TransferChecked {
+                from: token_wager.vault.to_account_info(),
+                mint: token_wager.mint.to_account_info(),
+                to: token_wager.token_account.to_account_info(),
+                authority: game_account.to_account_info(),
+            },
+            signer_seeds,
+        ),
+        amount,
+        token_wager.mint.decimals,
+    )
+}
+
+/// Moves Elo points from `loser` to `winner` and returns how many moved.
+fn rate_game(winner: &mut PlayerProfile, loser: &mut PlayerProfile) -> u32 {
+    let expected_bps = elo_expected_bps(winner.rating, loser.rating);
+    // Rounded `K * (1 - expected)`.
+    let change = (ELO_K * (10_000 - expected_bps) + 5_000) / 10_000;
+
+    winner.rating += change;
+    loser.rating = loser.rating.saturating_sub(change);
+    change
+}
+
+/// Expected score of a player rated `rating` against `opponent_rating`, in basis points.
+/// Interpolates `ELO_EXPECTED_BPS`, differences beyond 800 points are clamped.
+fn elo_expected_bps(rating: u32, opponent_rating: u32) -> u32 {
+    let diff = rating.abs_diff(opponent_rating).min(800);
+    let step = (diff / 25) as usize;
+    let remainder = diff % 25;
+    let expected_bps = if remainder == 0 {
+        ELO_EXPECTED_BPS[step]
+    } else {
+        let low = ELO_EXPECTED_BPS[step];
+        let high = ELO_EXPECTED_BPS[step + 1];
+        low + (high - low) * remainder / 25
+    };
+
+    if rating >= opponent_rating {
+        expected_bps
+    } else {
+        10_000 - expected_bps
+    }
+}
+
+/// The commitment `place_ships_ix` expects: sha256 of the fleet's cells followed by its salt.
+fn fleet_commitment(cells: &[[u8; 2]; MAX_FLEET_CELLS], salt: &[u8; 16]) -> [u8; 32] {
+    hashv(&[cells.as_flattened(), salt]).to_bytes()
+}
+
+/// Word and bit of `[row, col]` on a shot board, two `u128`s cover a 16x16 board.
+fn cell_bit(board_size: u8, row: u8, col: u8) -> (usize, u128) {
+    let index = row as u32 * board_size as u32 + col as u32;
+    ((index / 128) as usize, 1 << (index % 128))
+}
+
+/// Splits an `Enc<Mxe, PlayerShipFleet>` output into its nonce and ciphertexts.
+fn read_fleet_state(bytes: &[u8]) -> ([u8; 16], [[u8; 32]; FLEET_STATE_CIPHERTEXTS]) {
+    let nonce: [u8; 16] = bytes[0..16].try_into().unwrap();
+
+    let ciphertexts: [[u8; 32]; FLEET_STATE_CIPHERTEXTS] = bytes[16..FLEET_STATE_OUTPUT_LEN]
+        .chunks_exact(32)
+        .map(|c| c.try_into().unwrap())
+        .collect::<Vec<_>>()
+        .try_into()
+        .unwrap();
+
+    (nonce, ciphertexts)
+}
+
+#[derive(Accounts)]
+#[instruction(game_id: u64)]
+pub struct NewGame<'info> {
+    // the game pda creator, player1
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    // game account
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + GameData::INIT_SPACE,
+        seeds = [b"game_data_account", payer.key().as_ref(), &game_id.to_le_bytes()],
+        bump,
+    )]
+    pub game_account: Box<Account<'info, GameData>>,
+    // holds both players' wagers until the game is settled
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + WagerEscrow::INIT_SPACE,
+        seeds = [b"wager_escrow", game_account.key().as_ref()],
+        bump,
+    )]
+    pub escrow: Account<'info, WagerEscrow>,
+    #[account(
+        seeds = [b"program_config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + PlayerProfile::INIT_SPACE,
+        seeds = [b"player_profile", payer.key().as_ref()],
+        bump,
+    )]
+    pub player_profile: Box<Account<'info, PlayerProfile>>,
+    // the remaining accounts are only passed to wager an SPL token instead of lamports
+    pub wager_mint: Option<Box<Account<'info, Mint>>>,
+    #[account(
+        mut,
+        token::mint = wager_mint,
+        token::authority = payer,
+    )]
+    pub payer_token_account: Option<Box<Account<'info, TokenAccount>>>,
+    #[account(
+        init,
+        payer = payer,
+        associated_token::mint = wager_mint,
+        associated_token::authority = game_account,
+    )]
+    pub wager_vault: Option<Box<Account<'info, TokenAccount>>>,
+    pub token_program: Option<Program<'info, Token>>,
+    pub associated_token_program: Option<Program<'info, AssociatedToken>>,
+}
+
+#[callback_accounts("init_player_ship_fleet_location", payer)]
+#[derive(Accounts)]
+pub struct InitPlayerShipFleetLocCallback<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub arcium_program: Program<'info, Arcium>,
+    #[account(
+        address = derive_comp_def_pda!(COMP_DEF_OFFSET_INIT_PLAYER_SHIPS)
+    )]
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
+    /// CHECK: instructions_sysvar, checked by the account constraint
+    pub instructions_sysvar: AccountInfo<'info>,
     /// CallBack account
+    #[account(
+        mut,
+        seeds = [
+            b"game_data_account",
+            game_account.player_1.as_ref(),
+            &game_account.game_id.to_le_bytes(),
+        ],
+        bump = game_account.bump,
+    )]
+    pub game_account: Box<Account<'info, GameData>>,
+    #[account(
+        constraint = game_account.is_pending_computation(computation_account.key())
+            @ BattleShipErrorCode::UnexpectedComputation
+    )]
+    /// CHECK: computation_account, must be the computation `game_account` is waiting on.
+    pub computation_account: UncheckedAccount<'info>,
+}
+
+#[init_computation_definition_accounts("init_player_ship_fleet_location", payer)]
+#[derive(Accounts)]
+pub struct InitNewGameCompDef<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        address = derive_mxe_pda!()
+    )]
+    pub mxe_account: Box<Account<'info, PersistentMXEAccount>>,
+    #[account(mut)]
+    /// CHECK: comp_def_account, checked by arcium program.
+    /// Can't check it here as it's not initialized yet.
+    pub comp_def_account: UncheckedAccount<'info>,
+    pub arcium_program: Program<'info, Arcium>,
+    pub system_program: Program<'info, System>,
+}
+
+#[queue_computation_accounts("init_player_ship_fleet_location", payer)]
+#[derive(Accounts)]
+#[instruction(computation_offset: u64)]
+pub struct JoinGame<'info> {
+    // the joining or invited wallet, player2
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        address = derive_mxe_pda!()
+    )]
+    pub mxe_account: Account<'info, PersistentMXEAccount>,
+    #[account(
+        mut,
+        address = derive_mempool_pda!()
+    )]
+    /// CHECK: mempool_account, checked by the arcium program.
+    pub mempool_account: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        address = derive_execpool_pda!()
+    )]
+    /// CHECK: executing_pool, checked by the arcium program.
+    pub executing_pool: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        address = derive_comp_pda!(computation_offset)
+    )]
+    /// CHECK: computation_account, checked by the arcium program.
+    pub computation_account: UncheckedAccount<'info>,
+    #[account(
+        address = derive_comp_def_pda!(COMP_DEF_OFFSET_INIT_PLAYER_SHIPS) // for mxe cluster to access mxe bytecode and metadata
+    )]
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    #[account(
+        mut,
+        address = derive_cluster_pda!(mxe_account)
+    )]
+    pub cluster_account: Account<'info, Cluster>,
+    #[account(
+        mut,
+        address = ARCIUM_STAKING_POOL_ACCOUNT_ADDRESS,
+    )]
+    pub pool_account: Account<'info, StakingPoolAccount>,
+    #[account(
+        address = ARCIUM_CLOCK_ACCOUNT_ADDRESS
+    )]
+    pub clock_account: Account<'info, ClockAccount>,
+    pub system_program: Program<'info, System>,
+    pub arcium_program: Program<'info, Arcium>,
+    // game account
+    #[account(
+        mut,
+        seeds = [
+            b"game_data_account",
+            game_account.player_1.as_ref(),
+            &game_account.game_id.to_le_bytes(),
+        ],
+        bump = game_account.bump,
+    )]
+    pub game_account: Box<Account<'info, GameData>>,
+    #[account(
+        mut,
+        seeds = [b"wager_escrow", game_account.key().as_ref()],
+        bump = escrow.bump,
+    )]
+    pub escrow: Account<'info, WagerEscrow>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + PlayerProfile::INIT_SPACE,
+        seeds = [b"player_profile", payer.key().as_ref()],
+        bump,
+    )]
+    pub player_profile: Box<Account<'info, PlayerProfile>>,
+    // only for games wagering an SPL token
+    pub wager_mint: Option<Box<Account<'info, Mint>>>,
+    #[account(
+        mut,
+        token::mint = wager_mint,
+        token::authority = payer,
+    )]
+    pub payer_token_account: Option<Box<Account<'info, TokenAccount>>>,
+    #[account(
+        mut,
+        associated_token::mint = wager_mint,
+        associated_token::authority = game_account,
+    )]
+    pub wager_vault: Option<Box<Account<'info, TokenAccount>>>,
+    pub token_program: Option<Program<'info, Token>>,
+    #[account(
+        seeds = [b"program_config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[queue_computation_accounts("place_ships", payer)]
+#[derive(Accounts)]
+#[instruction(computation_offset: u64)]
+pub struct PlaceShipsIx<'info> {
+    // the game pda creator, player1
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        address = derive_mxe_pda!()
+    )]
+    pub mxe_account: Account<'info, PersistentMXEAccount>,
+    #[account(
+        mut,
+        address = derive_mempool_pda!()
+    )]
+    /// CHECK: mempool_account, checked by the arcium program.
+    pub mempool_account: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        address = derive_execpool_pda!()
+    )]
+    /// CHECK: executing_pool, checked by the arcium program.
+    pub executing_pool: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        address = derive_comp_pda!(computation_offset)
+    )]
+    /// CHECK: computation_account, checked by the arcium program.
+    pub computation_account: UncheckedAccount<'info>,
+    #[account(
+        address = derive_comp_def_pda!(COMP_DEF_OFFSET_PLACE_SHIPS) // for mxe cluster to access mxe bytecode and metadata
+    )]
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    #[account(
+        mut,
+        address = derive_cluster_pda!(mxe_account)
+    )]
+    pub cluster_account: Account<'info, Cluster>,
+    #[account(
+        mut,
+        address = ARCIUM_STAKING_POOL_ACCOUNT_ADDRESS,
+    )]
+    pub pool_account: Account<'info, StakingPoolAccount>,
+    #[account(
+        address = ARCIUM_CLOCK_ACCOUNT_ADDRESS
+    )]
+    pub clock_account: Account<'info, ClockAccount>,
+    pub system_program: Program<'info, System>,
+    pub arcium_program: Program<'info, Arcium>,
+    // game account
+    #[account(
+        mut,
+        seeds = [
+            b"game_data_account",
+            game_account.player_1.as_ref(),
+            &game_account.game_id.to_le_bytes(),
+        ],
+        bump = game_account.bump,
+    )]
+    pub game_account: Box<Account<'info, GameData>>,
+    #[account(
+        seeds = [b"program_config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[callback_accounts("place_ships", payer)]
+#[derive(Accounts)]
+pub struct PlaceShipsIxCallback<'info> {
     #[account(mut)]
-    pub game_account: Account<'info, GameData>,
+    pub payer: Signer<'info>,
+    pub arcium_program: Program<'info, Arcium>,
+    #[account(
+        address = derive_comp_def_pda!(COMP_DEF_OFFSET_PLACE_SHIPS)
+    )]
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
+    /// CHECK: instructions_sysvar, checked by the account constraint
+    pub instructions_sysvar: AccountInfo<'info>,
+    /// encrypted instruction cb
+    #[account(
+        mut,
+        seeds = [
+            b"game_data_account",
+            game_account.player_1.as_ref(),
+            &game_account.game_id.to_le_bytes(),
+        ],
+        bump = game_account.bump,
+    )]
+    pub game_account: Box<Account<'info, GameData>>,
+    #[account(
+        constraint = game_account.is_pending_computation(computation_account.key())
+            @ BattleShipErrorCode::UnexpectedComputation
+    )]
+    /// CHECK: computation_account, must be the computation `game_account` is waiting on.
+    pub computation_account: UncheckedAccount<'info>,
+    #[account(
+        seeds = [b"program_config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
 }
 
-#[init_computation_definition_accounts("init_player_ship_fleet_location", payer)]
+#[init_computation_definition_accounts("place_ships", payer)]
 #[derive(Accounts)]
-pub struct InitNewGameCompDef<'info> {
+pub struct InitPlaceShipsCompDef<'info> {
     #[account(mut)]
     pub payer: Signer<'info>,
     #[account(
@@ -381,10 +1784,10 @@ pub struct InitNewGameCompDef<'info> {
     pub system_program: Program<'info, System>,
 }
 
-#[queue_computation_accounts("place_ships", payer)]
+#[queue_computation_accounts("take_turn", payer)]
 #[derive(Accounts)]
 #[instruction(computation_offset: u64)]
-pub struct PlaceShipsIx<'info> {
+pub struct TakeTurnIx<'info> {
     // the game pda creator, player1
     #[account(mut)]
     pub payer: Signer<'info>,
@@ -412,7 +1815,7 @@ pub struct PlaceShipsIx<'info> {
     /// CHECK: computation_account, checked by the arcium program.
     pub computation_account: UncheckedAccount<'info>,
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_PLACE_SHIPS) // for mxe cluster to access mxe bytecode and metadata
+        address = derive_comp_def_pda!(COMP_DEF_OFFSET_TAKE_TURN) // for mxe cluster to access mxe bytecode and metadata
     )]
     pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
     #[account(
@@ -434,141 +1837,500 @@ pub struct PlaceShipsIx<'info> {
     // game account
     #[account(
         mut,
-        seeds = [b"game_data_account"],
+        seeds = [
+            b"game_data_account",
+            game_account.player_1.as_ref(),
+            &game_account.game_id.to_le_bytes(),
+        ],
         bump = game_account.bump,
     )]
-    pub game_account: Account<'info, GameData>,
+    pub game_account: Box<Account<'info, GameData>>,
+    #[account(
+        seeds = [b"program_config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(
+        seeds = [b"player_profile", game_account.player_1.as_ref()],
+        bump = player_1_profile.bump,
+    )]
+    pub player_1_profile: Box<Account<'info, PlayerProfile>>,
+    #[account(
+        seeds = [b"player_profile", game_account.player_2.as_ref()],
+        bump = player_2_profile.bump,
+    )]
+    pub player_2_profile: Box<Account<'info, PlayerProfile>>,
 }
 
-#[callback_accounts("place_ships", payer)]
+#[callback_accounts("take_turn", payer)]
 #[derive(Accounts)]
-pub struct PlaceShipsIxCallback<'info> {
+pub struct TakeTurnCallback<'info> {
     #[account(mut)]
     pub payer: Signer<'info>,
     pub arcium_program: Program<'info, Arcium>,
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_PLACE_SHIPS)
+        address = derive_comp_def_pda!(COMP_DEF_OFFSET_TAKE_TURN)
     )]
     pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
     #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
     /// CHECK: instructions_sysvar, checked by the account constraint
     pub instructions_sysvar: AccountInfo<'info>,
-    /// encrypted instruction cb
+    // encrypted ix callback
+    #[account(
+        mut,
+        seeds = [
+            b"game_data_account",
+            game_account.player_1.as_ref(),
+            &game_account.game_id.to_le_bytes(),
+        ],
+        bump = game_account.bump,
+    )]
+    pub game_account: Box<Account<'info, GameData>>,
+    #[account(
+        constraint = game_account.is_pending_computation(computation_account.key())
+            @ BattleShipErrorCode::UnexpectedComputation
+    )]
+    /// CHECK: computation_account, must be the computation `game_account` is waiting on.
+    pub computation_account: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"player_profile", game_account.player_1.as_ref()],
+        bump = player_1_profile.bump,
+    )]
+    pub player_1_profile: Box<Account<'info, PlayerProfile>>,
+    #[account(
+        mut,
+        seeds = [b"player_profile", game_account.player_2.as_ref()],
+        bump = player_2_profile.bump,
+    )]
+    pub player_2_profile: Box<Account<'info, PlayerProfile>>,
+    #[account(
+        seeds = [b"program_config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[init_computation_definition_accounts("take_turn", payer)]
+#[derive(Accounts)]
+pub struct InitTakeTurnCompDef<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        address = derive_mxe_pda!()
+    )]
+    pub mxe_account: Box<Account<'info, PersistentMXEAccount>>,
     #[account(mut)]
-    pub game_account: Account<'info, GameData>,
+    /// CHECK: comp_def_account, checked by arcium program.
+    /// Can't check it here as it's not initialized yet.
+    pub comp_def_account: UncheckedAccount<'info>,
+    pub arcium_program: Program<'info, Arcium>,
+    pub system_program: Program<'info, System>,
 }
 
-#[init_computation_definition_accounts("place_ships", payer)]
+#[queue_computation_accounts("reveal_fleets", payer)]
 #[derive(Accounts)]
-pub struct InitPlaceShipsCompDef<'info> {
+#[instruction(computation_offset: u64)]
+pub struct RevealFleetsIx<'info> {
+    // anyone auditing the finished game
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        address = derive_mxe_pda!()
+    )]
+    pub mxe_account: Account<'info, PersistentMXEAccount>,
+    #[account(
+        mut,
+        address = derive_mempool_pda!()
+    )]
+    /// CHECK: mempool_account, checked by the arcium program.
+    pub mempool_account: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        address = derive_execpool_pda!()
+    )]
+    /// CHECK: executing_pool, checked by the arcium program.
+    pub executing_pool: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        address = derive_comp_pda!(computation_offset)
+    )]
+    /// CHECK: computation_account, checked by the arcium program.
+    pub computation_account: UncheckedAccount<'info>,
+    #[account(
+        address = derive_comp_def_pda!(COMP_DEF_OFFSET_REVEAL_FLEETS) // for mxe cluster to access mxe bytecode and metadata
+    )]
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    #[account(
+        mut,
+        address = derive_cluster_pda!(mxe_account)
+    )]
+    pub cluster_account: Account<'info, Cluster>,
+    #[account(
+        mut,
+        address = ARCIUM_STAKING_POOL_ACCOUNT_ADDRESS,
+    )]
+    pub pool_account: Account<'info, StakingPoolAccount>,
+    #[account(
+        address = ARCIUM_CLOCK_ACCOUNT_ADDRESS
+    )]
+    pub clock_account: Account<'info, ClockAccount>,
+    pub system_program: Program<'info, System>,
+    pub arcium_program: Program<'info, Arcium>,
+    // game account
+    #[account(
+        mut,
+        seeds = [
+            b"game_data_account",
+            game_account.player_1.as_ref(),
+            &game_account.game_id.to_le_bytes(),
+        ],
+        bump = game_account.bump,
+    )]
+    pub game_account: Box<Account<'info, GameData>>,
+}
+
+#[callback_accounts("reveal_fleets", payer)]
+#[derive(Accounts)]
+pub struct RevealFleetsCallback<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub arcium_program: Program<'info, Arcium>,
+    #[account(
+        address = derive_comp_def_pda!(COMP_DEF_OFFSET_REVEAL_FLEETS)
+    )]
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
+    /// CHECK: instructions_sysvar, checked by the account constraint
+    pub instructions_sysvar: AccountInfo<'info>,
+    // encrypted ix callback
+    #[account(
+        mut,
+        seeds = [
+            b"game_data_account",
+            game_account.player_1.as_ref(),
+            &game_account.game_id.to_le_bytes(),
+        ],
+        bump = game_account.bump,
+    )]
+    pub game_account: Box<Account<'info, GameData>>,
+    #[account(
+        constraint = game_account.is_pending_computation(computation_account.key())
+            @ BattleShipErrorCode::UnexpectedComputation
+    )]
+    /// CHECK: computation_account, must be the computation `game_account` is waiting on.
+    pub computation_account: UncheckedAccount<'info>,
+}
+
+#[init_computation_definition_accounts("reveal_fleets", payer)]
+#[derive(Accounts)]
+pub struct InitRevealFleetsCompDef<'info> {
     #[account(mut)]
     pub payer: Signer<'info>,
     #[account(
         mut,
-        address = derive_mxe_pda!()
+        address = derive_mxe_pda!()
+    )]
+    pub mxe_account: Box<Account<'info, PersistentMXEAccount>>,
+    #[account(mut)]
+    /// CHECK: comp_def_account, checked by arcium program.
+    /// Can't check it here as it's not initialized yet.
+    pub comp_def_account: UncheckedAccount<'info>,
+    pub arcium_program: Program<'info, Arcium>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelInvite<'info> {
+    // `player_1` cancelling or `player_2` declining
+    pub player: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"game_data_account",
+            game_account.player_1.as_ref(),
+            &game_account.game_id.to_le_bytes(),
+        ],
+        bump = game_account.bump,
+    )]
+    pub game_account: Box<Account<'info, GameData>>,
+}
+
+#[derive(Accounts)]
+pub struct Resign<'info> {
+    // either player, the opponent wins
+    pub player: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"game_data_account",
+            game_account.player_1.as_ref(),
+            &game_account.game_id.to_le_bytes(),
+        ],
+        bump = game_account.bump,
+    )]
+    pub game_account: Box<Account<'info, GameData>>,
+    #[account(
+        mut,
+        seeds = [b"player_profile", game_account.player_1.as_ref()],
+        bump = player_1_profile.bump,
+    )]
+    pub player_1_profile: Box<Account<'info, PlayerProfile>>,
+    #[account(
+        mut,
+        seeds = [b"player_profile", game_account.player_2.as_ref()],
+        bump = player_2_profile.bump,
+    )]
+    pub player_2_profile: Box<Account<'info, PlayerProfile>>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimTimeout<'info> {
+    // the player waiting on a stalled opponent
+    pub player: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"game_data_account",
+            game_account.player_1.as_ref(),
+            &game_account.game_id.to_le_bytes(),
+        ],
+        bump = game_account.bump,
+    )]
+    pub game_account: Box<Account<'info, GameData>>,
+    #[account(
+        mut,
+        seeds = [b"player_profile", game_account.player_1.as_ref()],
+        bump = player_1_profile.bump,
+    )]
+    pub player_1_profile: Box<Account<'info, PlayerProfile>>,
+    #[account(
+        mut,
+        seeds = [b"player_profile", game_account.player_2.as_ref()],
+        bump = player_2_profile.bump,
+    )]
+    pub player_2_profile: Box<Account<'info, PlayerProfile>>,
+    // pause time, which does not count against the deadline
+    #[account(
+        seeds = [b"program_config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct CloseGame<'info> {
+    // either player
+    pub player: Signer<'info>,
+    // payer of `new_game`, receives the rent
+    #[account(
+        mut,
+        address = game_account.player_1,
+    )]
+    pub player_1: SystemAccount<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"game_data_account",
+            game_account.player_1.as_ref(),
+            &game_account.game_id.to_le_bytes(),
+        ],
+        bump = game_account.bump,
+        close = player_1,
+    )]
+    pub game_account: Box<Account<'info, GameData>>,
+    #[account(
+        mut,
+        seeds = [b"wager_escrow", game_account.key().as_ref()],
+        bump = escrow.bump,
+        close = player_1,
+    )]
+    pub escrow: Account<'info, WagerEscrow>,
+    // only for games wagering an SPL token, the vault's rent and leftover tokens also go to player 1
+    pub wager_mint: Option<Box<Account<'info, Mint>>>,
+    #[account(
+        mut,
+        token::mint = wager_mint,
+        token::authority = player_1,
+    )]
+    pub player_1_token_account: Option<Box<Account<'info, TokenAccount>>>,
+    #[account(
+        mut,
+        associated_token::mint = wager_mint,
+        associated_token::authority = game_account,
+    )]
+    pub wager_vault: Option<Box<Account<'info, TokenAccount>>>,
+    pub token_program: Option<Program<'info, Token>>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimWinnings<'info> {
+    #[account(mut)]
+    pub winner: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"game_data_account",
+            game_account.player_1.as_ref(),
+            &game_account.game_id.to_le_bytes(),
+        ],
+        bump = game_account.bump,
+    )]
+    pub game_account: Box<Account<'info, GameData>>,
+    #[account(
+        mut,
+        seeds = [b"wager_escrow", game_account.key().as_ref()],
+        bump = escrow.bump,
+    )]
+    pub escrow: Account<'info, WagerEscrow>,
+    // only for games wagering an SPL token
+    pub wager_mint: Option<Box<Account<'info, Mint>>>,
+    #[account(
+        mut,
+        token::mint = wager_mint,
+        token::authority = winner,
+    )]
+    pub winner_token_account: Option<Box<Account<'info, TokenAccount>>>,
+    #[account(
+        mut,
+        associated_token::mint = wager_mint,
+        associated_token::authority = game_account,
     )]
-    pub mxe_account: Box<Account<'info, PersistentMXEAccount>>,
-    #[account(mut)]
-    /// CHECK: comp_def_account, checked by arcium program.
-    /// Can't check it here as it's not initialized yet.
-    pub comp_def_account: UncheckedAccount<'info>,
-    pub arcium_program: Program<'info, Arcium>,
-    pub system_program: Program<'info, System>,
+    pub wager_vault: Option<Box<Account<'info, TokenAccount>>>,
+    pub token_program: Option<Program<'info, Token>>,
+    #[account(
+        seeds = [b"program_config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    // receives the protocol fee of lamport wagers
+    #[account(
+        mut,
+        address = config.treasury,
+    )]
+    pub treasury: SystemAccount<'info>,
+    // receives the protocol fee of SPL wagers
+    #[account(
+        mut,
+        token::mint = wager_mint,
+        token::authority = treasury,
+    )]
+    pub treasury_token_account: Option<Box<Account<'info, TokenAccount>>>,
 }
 
-#[queue_computation_accounts("take_turn", payer)]
 #[derive(Accounts)]
-#[instruction(computation_offset: u64)]
-pub struct TakeTurnIx<'info> {
-    // the game pda creator, player1
-    #[account(mut)]
-    pub payer: Signer<'info>,
-
+pub struct RefundWager<'info> {
     #[account(
-        address = derive_mxe_pda!()
+        mut,
+        address = game_account.player_1,
     )]
-    pub mxe_account: Account<'info, PersistentMXEAccount>,
+    pub player_1: SystemAccount<'info>,
+    // only required once `player_2` has deposited
     #[account(
         mut,
-        address = derive_mempool_pda!()
+        address = game_account.player_2,
     )]
-    /// CHECK: mempool_account, checked by the arcium program.
-    pub mempool_account: UncheckedAccount<'info>,
+    pub player_2: Option<SystemAccount<'info>>,
     #[account(
         mut,
-        address = derive_execpool_pda!()
+        seeds = [
+            b"game_data_account",
+            game_account.player_1.as_ref(),
+            &game_account.game_id.to_le_bytes(),
+        ],
+        bump = game_account.bump,
     )]
-    /// CHECK: executing_pool, checked by the arcium program.
-    pub executing_pool: UncheckedAccount<'info>,
+    pub game_account: Box<Account<'info, GameData>>,
     #[account(
         mut,
-        address = derive_comp_pda!(computation_offset)
+        seeds = [b"wager_escrow", game_account.key().as_ref()],
+        bump = escrow.bump,
     )]
-    /// CHECK: computation_account, checked by the arcium program.
-    pub computation_account: UncheckedAccount<'info>,
+    pub escrow: Account<'info, WagerEscrow>,
+    // only for games wagering an SPL token
+    pub wager_mint: Option<Box<Account<'info, Mint>>>,
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_TAKE_TURN) // for mxe cluster to access mxe bytecode and metadata
+        mut,
+        token::mint = wager_mint,
+        token::authority = player_1,
     )]
-    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    pub player_1_token_account: Option<Box<Account<'info, TokenAccount>>>,
     #[account(
         mut,
-        address = derive_cluster_pda!(mxe_account)
+        token::mint = wager_mint,
+        token::authority = player_2,
     )]
-    pub cluster_account: Account<'info, Cluster>,
+    pub player_2_token_account: Option<Box<Account<'info, TokenAccount>>>,
     #[account(
         mut,
-        address = ARCIUM_STAKING_POOL_ACCOUNT_ADDRESS,
+        associated_token::mint = wager_mint,
+        associated_token::authority = game_account,
     )]
-    pub pool_account: Account<'info, StakingPoolAccount>,
+    pub wager_vault: Option<Box<Account<'info, TokenAccount>>>,
+    pub token_program: Option<Program<'info, Token>>,
+}
+
+#[derive(Accounts)]
+pub struct InitConfig<'info> {
+    // only the upgrade authority of this program may create the config
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::ConfidentialBattleshipGame>,
     #[account(
-        address = ARCIUM_CLOCK_ACCOUNT_ADDRESS
+        constraint = program_data.upgrade_authority_address == Some(authority.key())
+            @ BattleShipErrorCode::UnauthorizedAdmin
     )]
-    pub clock_account: Account<'info, ClockAccount>,
+    pub program_data: Account<'info, ProgramData>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + ProgramConfig::INIT_SPACE,
+        seeds = [b"program_config"],
+        bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    // receives the protocol fees, must already hold its rent-exempt minimum
+    pub treasury: SystemAccount<'info>,
     pub system_program: Program<'info, System>,
-    pub arcium_program: Program<'info, Arcium>,
-    // game account
+}
+
+#[derive(Accounts)]
+pub struct SetConfig<'info> {
+    pub authority: Signer<'info>,
     #[account(
         mut,
-        seeds = [b"game_data_account"],
-        bump = game_account.bump,
+        seeds = [b"program_config"],
+        bump = config.bump,
     )]
-    pub game_account: Account<'info, GameData>,
+    pub config: Account<'info, ProgramConfig>,
+    // receives the protocol fees, must already hold its rent-exempt minimum
+    pub treasury: SystemAccount<'info>,
 }
 
-#[callback_accounts("take_turn", payer)]
 #[derive(Accounts)]
-pub struct TakeTurnCallback<'info> {
-    #[account(mut)]
-    pub payer: Signer<'info>,
-    pub arcium_program: Program<'info, Arcium>,
+pub struct UpdateConfig<'info> {
+    pub authority: Signer<'info>,
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_TAKE_TURN)
+        mut,
+        seeds = [b"program_config"],
+        bump = config.bump,
     )]
-    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
-    #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
-    /// CHECK: instructions_sysvar, checked by the account constraint
-    pub instructions_sysvar: AccountInfo<'info>,
-    // encrypted ix callback
-    #[account(mut)]
-    pub game_account: Account<'info, GameData>,
+    pub config: Account<'info, ProgramConfig>,
 }
 
-#[init_computation_definition_accounts("take_turn", payer)]
 #[derive(Accounts)]
-pub struct InitTakeTurnCompDef<'info> {
-    #[account(mut)]
-    pub payer: Signer<'info>,
+pub struct AcceptAuthority<'info> {
+    // must match `config.pending_authority`
+    pub new_authority: Signer<'info>,
     #[account(
         mut,
-        address = derive_mxe_pda!()
+        seeds = [b"program_config"],
+        bump = config.bump,
     )]
-    pub mxe_account: Box<Account<'info, PersistentMXEAccount>>,
-    #[account(mut)]
-    /// CHECK: comp_def_account, checked by arcium program.
-    /// Can't check it here as it's not initialized yet.
-    pub comp_def_account: UncheckedAccount<'info>,
-    pub arcium_program: Program<'info, Arcium>,
-    pub system_program: Program<'info, System>,
+    pub config: Account<'info, ProgramConfig>,
 }
 
 #[account]
@@ -585,10 +2347,315 @@ pub struct GameData {
 
     // Nonce for mxe to decrypt ships
     pub fleet_state_nonce: [u8; 16], // 16
-    // each player have 17 possible locations on the matrix of 10*10
-    // each location will be of `[u8; 32] - cipher text`
-    // so we can store the [[u8; 32]; 17+17] in a single state
-    pub encrypted_fleet_state: [[u8; 32]; 34],
+    // each player have up to 19 ship cells on the board (see `FleetPreset`)
+    // each location is a `[row, col]` pair, packed with the ship health into 3 `u128`s
+    // per player, and every `u128` is a `[u8; 32]` cipher text, salts included
+    pub encrypted_fleet_state: [[u8; 32]; FLEET_STATE_CIPHERTEXTS],
+
+    pub player_1_placed: bool, // set by `place_ships_callback`
+    pub player_2_placed: bool,
+    pub game_id: u64, // PDA seed together with `player_1`
+
+    // Offset of the queued computation whose callback may write to this game,
+    // while set no other instruction may queue work against the fleet state
+    pub pending_computation: Option<u64>,
+
+    // Public shot boards, bit `row * board_size + col` (counting across both words)
+    // is set once a player fired at that cell, and also in `*_hits` if the shot hit a ship
+    pub player_1_shots: [u128; 2],
+    pub player_1_hits: [u128; 2],
+    pub player_2_shots: [u128; 2],
+    pub player_2_hits: [u128; 2],
+    pub last_guess: [u8; 2], // cell of the shot awaiting `take_turn_callback`
+
+    // Bit `slot` is set once the ship in that slot of the player's own fleet is sunk
+    pub player_1_sunk_ships: u8,
+    pub player_2_sunk_ships: u8,
+
+    pub winner: Option<Pubkey>,
+    pub finish_reason: Option<FinishReason>,
+    pub turn_count: u32,  // turns resolved by `take_turn_callback`
+    pub finished_at: i64, // unix timestamp, 0 until the game is finished
+
+    pub move_timeout: i64,  // seconds allowed per placement or turn
+    pub move_deadline: i64, // unix timestamp after which `claim_timeout` is allowed
+
+    pub wager: u64,                 // stake per player, 0 for a friendly game
+    pub player_2_deposited: bool,   // `player_1` deposits when creating the game
+    pub wager_settled: bool,        // pot paid out or deposits refunded
+    pub wager_mint: Option<Pubkey>, // SPL mint of the wager, `None` for lamports
+    pub fee_bps: u16,               // protocol fee on the pot, copied from `ProgramConfig`
+    pub ranked: bool,               // the result moves both players' Elo ratings
+    pub fleets_revealed: bool,      // `reveal_fleets` has published both fleets
+
+    pub player_1_commitment: [u8; 32], // sha256 of the placed cells and salt, checked on reveal
+    pub player_2_commitment: [u8; 32],
+
+    pub board_size: u8, // rows and columns of the square board
+    pub fleet_preset: FleetPreset,
+    // `ProgramConfig::paused_seconds_at` when `move_deadline` was set; pauses since push
+    // the deadline back
+    pub deadline_paused_seconds: i64,
+}
+
+/// Lifetime statistics of one wallet, created with its first game.
+#[account]
+#[derive(InitSpace)]
+pub struct PlayerProfile {
+    pub bump: u8,
+    pub player: Pubkey,
+    pub games_played: u32, // finished games, cancelled ones are not counted
+    pub wins: u32,
+    pub losses: u32,
+    pub forfeits: u32, // losses by resignation
+    pub shots: u32,
+    pub hits: u32,
+    pub accuracy_bps: u16, // hits / shots in basis points
+    pub rating: u32,       // Elo rating, only ranked games move it
+}
+
+impl PlayerProfile {
+    /// Fills in a profile created by `init_if_needed`, existing profiles keep their stats.
+    pub fn init(&mut self, player: Pubkey, bump: u8) {
+        if self.player == Pubkey::default() {
+            self.rating = INITIAL_RATING;
+        }
+        self.player = player;
+        self.bump = bump;
+    }
+
+    /// Counts a shot fired by this player.
+    pub fn record_shot(&mut self, was_hit: bool) {
+        self.shots += 1;
+        if was_hit {
+            self.hits += 1;
+        }
+        self.accuracy_bps = (self.hits as u64 * 10_000 / self.shots as u64) as u16;
+    }
+
+    /// Counts a finished game this player took part in.
+    pub fn record_game(&mut self, won: bool, reason: FinishReason) {
+        self.games_played += 1;
+        if won {
+            self.wins += 1;
+        } else {
+            self.losses += 1;
+            if reason == FinishReason::Forfeit {
+                self.forfeits += 1;
+            }
+        }
+    }
+}
+
+/// Program-wide settings, a single PDA administered by `authority`.
+#[account]
+#[derive(InitSpace)]
+pub struct ProgramConfig {
+    pub bump: u8,
+    pub authority: Pubkey,
+    pub treasury: Pubkey,                  // wallet collecting protocol fees
+    pub fee_bps: u16,                      // fee on wagered pots for newly created games
+    pub default_move_timeout: i64,         // used when `new_game` passes a `move_timeout` of 0
+    pub allowed_modes: u8,                 // `MODE_*` bits of the games that may be created
+    pub paused: bool,                      // stops new games, placements and turns
+    pub pending_authority: Option<Pubkey>, // proposed admin, until it calls `accept_authority`
+    pub paused_at: i64,                    // unix timestamp the current pause started at
+    pub paused_seconds: i64,               // total length of all finished pauses
+}
+
+impl ProgramConfig {
+    /// Validates and stores the admin-controlled settings.
+    pub fn set(
+        &mut self,
+        treasury: &SystemAccount,
+        fee_bps: u16,
+        default_move_timeout: i64,
+        allowed_modes: u8,
+        paused: bool,
+    ) -> Result<()> {
+        require!(fee_bps <= MAX_FEE_BPS, BattleShipErrorCode::InvalidFee);
+        require!(
+            default_move_timeout > 0,
+            BattleShipErrorCode::InvalidTimeout
+        );
+        // Lamport fees are paid with `add_lamports`, which fails while the sum stays below
+        // the rent-exempt minimum, so an empty treasury would lock every small pot.
+        require!(
+            treasury.lamports() >= Rent::get()?.minimum_balance(0),
+            BattleShipErrorCode::TreasuryNotRentExempt
+        );
+        let treasury = treasury.key();
+
+        self.update_pause(paused)?;
+
+        self.treasury = treasury;
+        self.fee_bps = fee_bps;
+        self.default_move_timeout = default_move_timeout;
+        self.allowed_modes = allowed_modes;
+
+        emit!(ConfigUpdated {
+            authority: self.authority,
+            treasury,
+            fee_bps,
+            default_move_timeout,
+            allowed_modes,
+            paused,
+        });
+
+        Ok(())
+    }
+
+    /// Flips the pause switch and keeps count of the paused seconds, which move
+    /// deadlines skip since nobody can move while paused.
+    pub fn update_pause(&mut self, paused: bool) -> Result<()> {
+        if paused == self.paused {
+            return Ok(());
+        }
+
+        let now = Clock::get()?.unix_timestamp;
+        if paused {
+            self.paused_at = now;
+        } else {
+            self.paused_seconds += now - self.paused_at;
+        }
+        self.paused = paused;
+
+        emit!(PauseToggled {
+            paused,
+            authority: self.authority,
+        });
+
+        Ok(())
+    }
+
+    /// Seconds spent paused up to `now`, the ongoing pause included.
+    pub fn paused_seconds_at(&self, now: i64) -> i64 {
+        if self.paused {
+            self.paused_seconds + (now - self.paused_at)
+        } else {
+            self.paused_seconds
+        }
+    }
+
+    /// Checks a game of `mode` may be created and resolves its move timeout.
+    pub fn check_new_game(
+        &self,
+        mode: u8,
+        move_timeout: i64,
+        wager: u64,
+        ranked: bool,
+    ) -> Result<i64> {
+        require!(!self.paused, BattleShipErrorCode::ProgramPaused);
+        require!(
+            self.allowed_modes & mode != 0,
+            BattleShipErrorCode::ModeDisabled
+        );
+        require!(
+            wager == 0 || self.allowed_modes & MODE_WAGER != 0,
+            BattleShipErrorCode::ModeDisabled
+        );
+        require!(
+            !ranked || self.allowed_modes & MODE_RANKED != 0,
+            BattleShipErrorCode::ModeDisabled
+        );
+
+        let move_timeout = if move_timeout == 0 {
+            self.default_move_timeout
+        } else {
+            move_timeout
+        };
+        require!(move_timeout > 0, BattleShipErrorCode::InvalidTimeout);
+
+        Ok(move_timeout)
+    }
+}
+
+/// Program-owned PDA holding the wagered lamports of one game.
+/// SPL wagers sit in the game PDA's associated token account instead.
+#[account]
+#[derive(InitSpace)]
+pub struct WagerEscrow {
+    pub bump: u8,
+}
+
+impl GameData {
+    /// Ends the game in favour of `winner`, records it on both profiles and announces it.
+    pub fn finish(
+        &mut self,
+        game_pda: Pubkey,
+        winner: Pubkey,
+        reason: FinishReason,
+        player_1_profile: &mut PlayerProfile,
+        player_2_profile: &mut PlayerProfile,
+    ) -> Result<()> {
+        self.game_state = GameState::Finished;
+        self.winner = Some(winner);
+        self.finish_reason = Some(reason);
+        self.finished_at = Clock::get()?.unix_timestamp;
+
+        let player_1_won = winner == self.player_1;
+        player_1_profile.record_game(player_1_won, reason);
+        player_2_profile.record_game(!player_1_won, reason);
+
+        let rating_change = if !self.ranked {
+            0
+        } else if player_1_won {
+            rate_game(player_1_profile, player_2_profile)
+        } else {
+            rate_game(player_2_profile, player_1_profile)
+        };
+
+        emit!(GameFinished {
+            game_pda,
+            winner,
+            reason,
+            turn_count: self.turn_count,
+            finished_at: self.finished_at,
+            fee: self.pot_fee(),
+            rating_change,
+            player_1_rating: player_1_profile.rating,
+            player_2_rating: player_2_profile.rating,
+        });
+
+        Ok(())
+    }
+
+    /// Protocol fee withheld from the pot when the winner claims it.
+    pub fn pot_fee(&self) -> u64 {
+        (self.wager as u128 * 2 * self.fee_bps as u128 / 10_000) as u64
+    }
+
+    /// Gives the player to move a fresh `move_timeout` from now.
+    pub fn reset_move_deadline(&mut self, config: &ProgramConfig) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        self.move_deadline = now.saturating_add(self.move_timeout);
+        self.deadline_paused_seconds = config.paused_seconds_at(now);
+        Ok(())
+    }
+
+    /// Whether `move_deadline` has passed at `now`, not counting the time the program
+    /// spent paused since it was set.
+    pub fn is_past_deadline(&self, config: &ProgramConfig, now: i64) -> bool {
+        let paused = config.paused_seconds_at(now) - self.deadline_paused_seconds;
+        now > self.move_deadline.saturating_add(paused)
+    }
+
+    /// Releases the lock of a computation the Arcium network aborted. Its output is
+    /// dropped, so the fleet state stays as it was and the move may be retried.
+    pub fn abort_computation(&mut self, game_pda: Pubkey) {
+        if let Some(computation_offset) = self.pending_computation.take() {
+            emit!(ComputationAborted {
+                game_pda,
+                computation_offset,
+            });
+        }
+    }
+
+    /// Whether `computation_account` is the computation this game queued last.
+    pub fn is_pending_computation(&self, computation_account: Pubkey) -> bool {
+        self.pending_computation
+            .is_some_and(|offset| derive_comp_pda!(offset) == computation_account)
+    }
 }
 
 #[derive(InitSpace, AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
@@ -597,10 +2664,90 @@ pub enum GameState {
     Player1Turn = 1,
     Player2Turn = 2,
     Finished = 3,
+    WaitingForOpponent = 4, // open game, anyone may `join_game`
+    InvitePending = 5,      // named `player_2` has not accepted yet
+    Cancelled = 6,          // invite declined or cancelled before placement
+}
+
+#[derive(InitSpace, AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FinishReason {
+    AllShipsSunk = 0,
+    Forfeit = 1,
+    Timeout = 2,
+}
+
+/// Ships each player places, chosen by the game creator. Mirrors the tables of the
+/// `place_ships` circuit, so variants may only be appended.
+#[derive(InitSpace, AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FleetPreset {
+    Classic = 0,       // 5, 4, 3, 3, 2
+    MiltonBradley = 1, // 5, 4, 3, 2, 2, 1, 1
+    Small = 2,         // 3, 2, 2
+}
+
+impl FleetPreset {
+    /// Ship length per slot in the order `place_ships` expects them, 0 for an empty slot.
+    pub fn ship_lengths(self) -> [u8; MAX_FLEET_SHIPS] {
+        match self {
+            FleetPreset::Classic => [5, 4, 3, 3, 2, 0, 0],
+            FleetPreset::MiltonBradley => [5, 4, 3, 2, 2, 1, 1],
+            FleetPreset::Small => [3, 2, 2, 0, 0, 0, 0],
+        }
+    }
+
+    /// Kind of ship in each slot, `None` for an empty slot.
+    pub fn ship_kinds(self) -> [Option<ShipKind>; MAX_FLEET_SHIPS] {
+        use ShipKind::*;
+        match self {
+            FleetPreset::Classic => [
+                Some(Carrier),
+                Some(Battleship),
+                Some(Cruiser),
+                Some(Submarine),
+                Some(Destroyer),
+                None,
+                None,
+            ],
+            FleetPreset::MiltonBradley => [
+                Some(Carrier),
+                Some(Battleship),
+                Some(Cruiser),
+                Some(Destroyer),
+                Some(Destroyer),
+                Some(Submarine),
+                Some(Submarine),
+            ],
+            FleetPreset::Small => [
+                Some(Cruiser),
+                Some(Destroyer),
+                Some(Destroyer),
+                None,
+                None,
+                None,
+                None,
+            ],
+        }
+    }
+
+    /// Cells that must be hit to sink the whole fleet.
+    pub fn cells(self) -> u8 {
+        self.ship_lengths().iter().sum()
+    }
+}
+
+/// Kinds of ship a `FleetPreset` is made of, reported when one sinks.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShipKind {
+    Carrier = 0,
+    Battleship = 1,
+    Cruiser = 2,
+    Submarine = 3,
+    Destroyer = 4,
 }
 
 #[error_code]
 pub enum BattleShipErrorCode {
+    // No longer raised since callbacks handle aborts, kept so the codes below keep their numbers.
     #[msg("The computation was aborted by the Arcium network.")]
     AbortedComputation,
     #[msg("The game is not in the correct state for this action.")]
@@ -609,25 +2756,177 @@ pub enum BattleShipErrorCode {
     InvalidTurn,
     #[msg("The transaction was signed by an unauthorized player.")]
     UnauthorizedPlayer,
+    #[msg("This player has already placed their ships.")]
+    ShipsAlreadyPlaced,
+    #[msg("The callback does not belong to the computation this game is waiting on.")]
+    UnexpectedComputation,
+    #[msg("A computation is still pending for this game.")]
+    ComputationPending,
+    #[msg("The guessed cell is outside the board.")]
+    InvalidCoordinates,
+    #[msg("This player has already fired at that cell.")]
+    CellAlreadyFired,
+    #[msg("The move timeout must be positive.")]
+    InvalidTimeout,
+    #[msg("The opponent still has time to move.")]
+    DeadlineNotReached,
+    #[msg("The game is not waiting on this player's opponent.")]
+    NotWaitingOnOpponent,
+    #[msg("This game has no wager.")]
+    NoWager,
+    #[msg("The wager has already been paid out or refunded.")]
+    WagerSettled,
+    #[msg("The wager must be paid out or refunded first.")]
+    WagerUnsettled,
+    #[msg("Only the winner can claim the pot.")]
+    NotWinner,
+    #[msg("player_2 must be passed to refund their deposit.")]
+    MissingPlayer2,
+    #[msg("The wager's token accounts must be passed.")]
+    MissingTokenAccounts,
+    #[msg("The mint is not the game's wager mint.")]
+    WrongWagerMint,
+    #[msg("Only the config authority can do this.")]
+    UnauthorizedAdmin,
+    #[msg("The fee exceeds the maximum basis points.")]
+    InvalidFee,
+    #[msg("The program is paused.")]
+    ProgramPaused,
+    #[msg("This kind of game is disabled.")]
+    ModeDisabled,
+    #[msg("The fleets of this game have already been revealed.")]
+    FleetsAlreadyRevealed,
+    #[msg("Boards must be between 8x8 and 16x16.")]
+    InvalidBoardSize,
+    #[msg("The treasury must hold its rent-exempt minimum.")]
+    TreasuryNotRentExempt,
 }
 
 #[event]
 pub struct GameCreated {
     game_pda: Pubkey,
+    game_id: u64,
+    player_1: Pubkey,
+    player_2: Pubkey,
+}
+
+#[event]
+pub struct InviteSent {
+    game_pda: Pubkey,
+    game_id: u64,
     player_1: Pubkey,
     player_2: Pubkey,
 }
 
+#[event]
+pub struct GameCancelled {
+    game_pda: Pubkey,
+    cancelled_by: Option<Pubkey>, // `None` when the game's fleet state could not be created
+}
+
+#[event]
+pub struct ComputationAborted {
+    game_pda: Pubkey,
+    computation_offset: u64,
+}
+
+#[event]
+pub struct GameOpened {
+    game_pda: Pubkey,
+    game_id: u64,
+    player_1: Pubkey,
+}
+
 #[event]
 pub struct ShipsPlaced {
     game_pda: Pubkey,
+    is_player_1: bool,
+}
+
+#[event]
+pub struct ShipsRejected {
+    game_pda: Pubkey,
+    is_player_1: bool,
+}
+
+#[event]
+pub struct BattleStarted {
+    game_pda: Pubkey,
+    first_turn: Pubkey,
 }
 
 #[event]
 pub struct TurnResult {
     game_pda: Pubkey,
+    row: u8,
+    col: u8,
     was_hit: bool,
+    sunk_ship: Option<ShipKind>,
     ships_left_player_1: u8,
     ships_left_player_2: u8,
     new_game_state: GameState,
 }
+
+#[event]
+pub struct FleetsRevealed {
+    game_pda: Pubkey,
+    player_1_fleet: [[u8; 2]; MAX_FLEET_CELLS], // cells as placed, slot by slot, `[255, 255]` if empty
+    player_2_fleet: [[u8; 2]; MAX_FLEET_CELLS],
+    // whether the revealed fleet and salt hash to the commitment given at placement
+    player_1_commitment_valid: bool,
+    player_2_commitment_valid: bool,
+}
+
+#[event]
+pub struct GameFinished {
+    game_pda: Pubkey,
+    winner: Pubkey,
+    reason: FinishReason,
+    turn_count: u32,
+    finished_at: i64,
+    fee: u64,           // withheld from the pot of a wagered game, 0 otherwise
+    rating_change: u32, // Elo points moved from the loser to the winner, 0 if unranked
+    player_1_rating: u32,
+    player_2_rating: u32,
+}
+
+#[event]
+pub struct WinningsClaimed {
+    game_pda: Pubkey,
+    winner: Pubkey,
+    amount: u64,
+    fee: u64,
+}
+
+#[event]
+pub struct WagerRefunded {
+    game_pda: Pubkey,
+}
+
+#[event]
+pub struct ConfigUpdated {
+    authority: Pubkey,
+    treasury: Pubkey,
+    fee_bps: u16,
+    default_move_timeout: i64,
+    allowed_modes: u8,
+    paused: bool,
+}
+
+#[event]
+pub struct AuthorityProposed {
+    authority: Pubkey,
+    pending_authority: Pubkey,
+}
+
+#[event]
+pub struct AuthorityTransferred {
+    previous_authority: Pubkey,
+    authority: Pubkey,
+}
+
+#[event]
+pub struct PauseToggled {
+    paused: bool,
+    authority: Pubkey,
+}
@@ -2,7 +2,7 @@ import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { PublicKey } from "@solana/web3.js";
 import { ConfidentialBattleshipGame } from "../target/types/confidential_battleship_game";
-import { randomBytes } from "crypto";
+import { createHash, randomBytes } from "crypto";
 import {
   awaitComputationFinalization,
   getArciumEnv,
@@ -20,10 +20,94 @@ import {
   getComputationAccAddress,
   x25519,
 } from "@arcium-hq/client";
+import {
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  TOKEN_PROGRAM_ID,
+  createMint,
+  getAccount,
+  getAssociatedTokenAddressSync,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
 import * as fs from "fs";
 import * as os from "os";
 import { expect } from "chai";
 
+// Bow and orientation of the ship in each of the 7 slots, ignored for slots
+// the game's preset leaves empty. Every ship lies along its own row, starting
+// in column 0.
+const FLEET = [
+  { row: 0, col: 0, vertical: false },
+  { row: 1, col: 0, vertical: false },
+  { row: 2, col: 0, vertical: false },
+  { row: 3, col: 0, vertical: false },
+  { row: 4, col: 0, vertical: false },
+  { row: 5, col: 0, vertical: false },
+  { row: 6, col: 0, vertical: false },
+];
+
+// Most cells a slot holds, and the ship length per slot of each `FleetPreset`.
+const SLOT_CAPACITY = [5, 4, 3, 3, 2, 1, 1];
+const SHIP_LENGTHS = {
+  classic: [5, 4, 3, 3, 2, 0, 0],
+  miltonBradley: [5, 4, 3, 2, 2, 1, 1],
+  small: [3, 2, 2, 0, 0, 0, 0],
+};
+type FleetPreset = keyof typeof SHIP_LENGTHS;
+type FleetPresetArg =
+  anchor.IdlTypes<ConfidentialBattleshipGame>["fleetPreset"];
+
+// The cells `fleet` occupies, in the order `reveal_fleets` returns them.
+const fleetCells = (fleetPreset: FleetPreset, fleet = FLEET) =>
+  fleet.flatMap((ship, i) =>
+    Array.from({ length: SLOT_CAPACITY[i] }, (_, offset) => {
+      if (offset >= SHIP_LENGTHS[fleetPreset][i]) {
+        return [255, 255];
+      }
+      return ship.vertical
+        ? [ship.row + offset, ship.col]
+        : [ship.row, ship.col + offset];
+    })
+  );
+const FLEET_CELLS = fleetCells("classic");
+
+// Seconds each player has to place their fleet or take a turn.
+const MOVE_TIMEOUT_SECS = 60 * 60;
+// Per-game timeout of the tests that wait for a deadline to pass.
+const SHORT_TIMEOUT_SECS = 2;
+// Long enough to set up a game and pause it before the deadline passes.
+const PAUSE_TIMEOUT_SECS = 20;
+
+// Lamports each player stakes in the wagered games.
+const WAGER = new anchor.BN(anchor.web3.LAMPORTS_PER_SOL / 10);
+
+// Protocol fee on wagered pots, 2.5%.
+const FEE_BPS = 250;
+
+// `ProgramConfig::allowed_modes` bits: invite, open, wagered and ranked games.
+const MODE_INVITE = 1 << 0;
+const MODE_OPEN = 1 << 1;
+const MODE_WAGER = 1 << 2;
+const MODE_RANKED = 1 << 3;
+const ALL_MODES = MODE_INVITE | MODE_OPEN | MODE_WAGER | MODE_RANKED;
+
+// How a test game is created, a friendly unranked classic 10x10 game by
+// default.
+type GameOptions = {
+  wager?: anchor.BN;
+  wagerMint?: PublicKey;
+  ranked?: boolean;
+  boardSize?: number;
+  fleetPreset?: FleetPreset;
+  moveTimeout?: number;
+};
+
+type Player = {
+  keypair: anchor.web3.Keypair;
+  arciumPublicKey: Uint8Array;
+  cipher: RescueCipher;
+};
+
 describe("ConfidentialBattleshipGame", () => {
   // Configure the client to use the local cluster.
   anchor.setProvider(anchor.AnchorProvider.env());
@@ -47,61 +131,999 @@ describe("ConfidentialBattleshipGame", () => {
   };
 
   const arciumEnv = getArciumEnv();
+  const mxePublicKey = new Uint8Array([
+    34, 56, 246, 3, 165, 122, 74, 68, 14, 81, 107, 73, 129, 145, 196, 4, 98,
+    253, 120, 15, 235, 108, 37, 198, 124, 111, 38, 1, 210, 143, 72, 87,
+  ]);
+
+  const owner = readKpJson(`${os.homedir()}/.config/solana/id.json`);
+  const treasury = anchor.web3.Keypair.generate().publicKey;
+  const potFee = WAGER.muln(2).muln(FEE_BPS).divn(10_000).toNumber();
+  // Holds the upgrade authority, the only key allowed to create the config.
+  const programData = PublicKey.findProgramAddressSync(
+    [program.programId.toBuffer()],
+    new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+  )[0];
 
-  it("Is initialized!", async () => {
-    const owner = readKpJson(`${os.homedir()}/.config/solana/id.json`);
+  before(async () => {
+    for (const circuit of [
+      "init_player_ship_fleet_location",
+      "place_ships",
+      "take_turn",
+      "reveal_fleets",
+    ]) {
+      console.log(`Initializing ${circuit} computation definition`);
+      const sig = await initCompDef(program, owner, circuit, false, false);
+      console.log(`${circuit} computation definition initialized with`, sig);
+    }
 
-    console.log("Initializing add together computation definition");
-    const initATSig = await initAddTogetherCompDef(program, owner, false, false);
-    console.log(
-      "Add together computation definition initialized with signature",
-      initATSig
+    // The treasury must be rent-exempt before it can collect small fees.
+    const sig = await provider.connection.requestAirdrop(
+      treasury,
+      anchor.web3.LAMPORTS_PER_SOL
     );
+    await provider.connection.confirmTransaction(sig, "confirmed");
+    await program.methods
+      .initConfig(FEE_BPS, new anchor.BN(MOVE_TIMEOUT_SECS), ALL_MODES)
+      .accountsPartial({ authority: owner.publicKey, programData, treasury })
+      .signers([owner])
+      .rpc({ commitment: "confirmed" });
+  });
 
-    const privateKey = x25519.utils.randomPrivateKey();
-    const publicKey = x25519.getPublicKey(privateKey);
-    const mxePublicKey = new Uint8Array([
-      34, 56, 246, 3, 165, 122, 74, 68, 14, 81, 107, 73, 129, 145, 196, 4, 98,
-      253, 120, 15, 235, 108, 37, 198, 124, 111, 38, 1, 210, 143, 72, 87,
-    ]);
-    const sharedSecret = x25519.getSharedSecret(privateKey, mxePublicKey);
-    const cipher = new RescueCipher(sharedSecret);
+  it("places both fleets and resolves the first shots", async () => {
+    const player1 = newPlayer(owner);
+    const player2 = newPlayer(await fundedKeypair());
+    const gamePda = await startGame(player1, player2);
+
+    await placeShips(gamePda, player1);
+    const battleStartedPromise = awaitEvent("battleStarted");
+    await placeShips(gamePda, player2);
+    const battleStarted = await battleStartedPromise;
+    expect(battleStarted.firstTurn.toBase58()).to.equal(
+      player1.keypair.publicKey.toBase58()
+    );
 
-    const val1 = BigInt(1);
-    const val2 = BigInt(2);
-    const plaintext = [val1, val2];
+    // A hit proves the plaintext arguments line up with `take_turn`.
+    const hit = await takeTurn(gamePda, player1, 0, 0);
+    expect(hit.wasHit).to.equal(true);
+    expect(hit.shipsLeftPlayer2).to.equal(16);
+    expect(hit.newGameState).to.deep.equal({ player2Turn: {} });
 
-    const nonce = randomBytes(16);
-    const ciphertext = cipher.encrypt(plaintext, nonce);
+    const miss = await takeTurn(gamePda, player2, 9, 9);
+    expect(miss.wasHit).to.equal(false);
+    expect(miss.shipsLeftPlayer1).to.equal(17);
+    expect(miss.newGameState).to.deep.equal({ player1Turn: {} });
+  });
+
+  it("rejects a repeated shot before queuing a computation", async () => {
+    const player1 = newPlayer(await fundedKeypair());
+    const player2 = newPlayer(await fundedKeypair());
+    const gamePda = await startGame(player1, player2);
+
+    await placeShips(gamePda, player1);
+    await placeShips(gamePda, player2);
+    await takeTurn(gamePda, player1, 0, 0);
+    // Each player has their own shot board, so the same cell is fair game.
+    await takeTurn(gamePda, player2, 0, 0);
 
-    const sumEventPromise = awaitEvent("sumEvent");
     const computationOffset = new anchor.BN(randomBytes(8), "hex");
+    try {
+      await program.methods
+        .takeTurnIx(computationOffset, 0, 0)
+        .accountsPartial({
+          payer: player1.keypair.publicKey,
+          gameAccount: gamePda,
+          ...queueAccounts(computationOffset, "take_turn"),
+        })
+        .signers([player1.keypair])
+        .rpc({ commitment: "confirmed" });
+      expect.fail("repeated shot was accepted");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("CellAlreadyFired");
+    }
+
+    // Still player 1's turn, with nothing queued.
+    const game = await program.account.gameData.fetch(gamePda);
+    expect(game.gameState).to.deep.equal({ player1Turn: {} });
+    expect(game.pendingComputation).to.equal(null);
+    expect(game.player1Shots[0].toString()).to.equal("1");
+    expect(game.player1Hits[0].toString()).to.equal("1");
+  });
+
+  it("names a ship only once it is sunk", async () => {
+    const player1 = newPlayer(await fundedKeypair());
+    const player2 = newPlayer(await fundedKeypair());
+    const gamePda = await startGame(player1, player2);
+
+    await placeShips(gamePda, player1);
+    await placeShips(gamePda, player2);
+
+    // The classic destroyer sits in slot 4, on row 4.
+    const firstHit = await takeTurn(gamePda, player1, 4, 0);
+    expect(firstHit.wasHit).to.equal(true);
+    expect(firstHit.sunkShip).to.equal(null);
+    await takeTurn(gamePda, player2, 9, 9);
+
+    const sinkingHit = await takeTurn(gamePda, player1, 4, 1);
+    expect(sinkingHit.wasHit).to.equal(true);
+    expect(sinkingHit.sunkShip).to.deep.equal({ destroyer: {} });
+
+    const game = await program.account.gameData.fetch(gamePda);
+    expect(game.player2SunkShips).to.equal(1 << 4);
+    expect(game.player1SunkShips).to.equal(0);
+  });
+
+  it("plays a small fleet until every ship is sunk", async () => {
+    const player1 = newPlayer(owner);
+    const player2 = newPlayer(await fundedKeypair());
+    const gamePda = await startGame(player1, player2, {
+      boardSize: 8,
+      fleetPreset: "small",
+    });
+
+    const game = await program.account.gameData.fetch(gamePda);
+    expect(game.fleetPreset).to.deep.equal({ small: {} });
+    expect(game.player1ShipsLeft).to.equal(7);
+    expect(game.player2ShipsLeft).to.equal(7);
+
+    // The cruiser would run from column 6 to 8, off the 8x8 board.
+    const offBoardFleet = FLEET.map((ship, i) =>
+      i === 0 ? { ...ship, col: 6 } : ship
+    );
+    const shipsRejectedPromise = awaitEvent("shipsRejected");
+    await queuePlacement(gamePda, player1, "small", offBoardFleet);
+    const shipsRejected = await shipsRejectedPromise;
+    expect(shipsRejected.isPlayer1).to.equal(true);
+    const rejectedGame = await program.account.gameData.fetch(gamePda);
+    expect(rejectedGame.player1Placed).to.equal(false);
 
-    const queueSig = await program.methods
-      .addTogether(
+    await placeShips(gamePda, player1, "small");
+    await placeShips(gamePda, player2, "small");
+
+    // Row 8 exists on the default 10x10 board but not on this one.
+    const computationOffset = new anchor.BN(randomBytes(8), "hex");
+    try {
+      await program.methods
+        .takeTurnIx(computationOffset, 8, 0)
+        .accountsPartial({
+          payer: player1.keypair.publicKey,
+          gameAccount: gamePda,
+          ...queueAccounts(computationOffset, "take_turn"),
+        })
+        .signers([player1.keypair])
+        .rpc({ commitment: "confirmed" });
+      expect.fail("fired off the board");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("InvalidCoordinates");
+    }
+
+    // Player 1 hits every cell of the 3, 2 and 2 cell ships, player 2 keeps
+    // missing along the last row.
+    const targets = fleetCells("small").filter(([row]) => row !== 255);
+    // The cruiser sinks with its third cell, each destroyer with its second.
+    const sunkShips = [
+      null,
+      null,
+      { cruiser: {} },
+      null,
+      { destroyer: {} },
+      null,
+      { destroyer: {} },
+    ];
+    const gameFinishedPromise = awaitEvent("gameFinished");
+    for (const [i, [row, col]] of targets.entries()) {
+      const hit = await takeTurn(gamePda, player1, row, col);
+      expect(hit.wasHit).to.equal(true);
+      expect(hit.shipsLeftPlayer2).to.equal(targets.length - i - 1);
+      expect(hit.sunkShip).to.deep.equal(sunkShips[i]);
+
+      if (i < targets.length - 1) {
+        const miss = await takeTurn(gamePda, player2, 7, i);
+        expect(miss.wasHit).to.equal(false);
+      }
+    }
+
+    const gameFinished = await gameFinishedPromise;
+    expect(gameFinished.winner.toBase58()).to.equal(
+      player1.keypair.publicKey.toBase58()
+    );
+    expect(gameFinished.reason).to.deep.equal({ allShipsSunk: {} });
+  });
+
+  it("lets a player resign while fleets are being placed", async () => {
+    const player1 = newPlayer(owner);
+    const player2 = newPlayer(await fundedKeypair());
+    const gamePda = await startGame(player1, player2);
+
+    await placeShips(gamePda, player1);
+    const gameFinished = await resign(gamePda, player2);
+    expect(gameFinished.winner.toBase58()).to.equal(
+      player1.keypair.publicKey.toBase58()
+    );
+    expect(gameFinished.reason).to.deep.equal({ forfeit: {} });
+
+    const game = await program.account.gameData.fetch(gamePda);
+    expect(game.gameState).to.deep.equal({ finished: {} });
+    expect(game.winner.toBase58()).to.equal(
+      player1.keypair.publicKey.toBase58()
+    );
+
+    // Either player may close the finished game, rent goes to player 1.
+    await program.methods
+      .closeGame()
+      .accountsPartial({
+        player: player2.keypair.publicKey,
+        player1: player1.keypair.publicKey,
+        gameAccount: gamePda,
+      })
+      .signers([player2.keypair])
+      .rpc({ commitment: "confirmed" });
+    expect(await provider.connection.getAccountInfo(gamePda)).to.equal(null);
+  });
+
+  it("lets a player resign mid-battle", async () => {
+    const player1 = newPlayer(await fundedKeypair());
+    const player2 = newPlayer(await fundedKeypair());
+    const gamePda = await startGame(player1, player2);
+
+    await placeShips(gamePda, player1);
+    await placeShips(gamePda, player2);
+    await takeTurn(gamePda, player1, 0, 0);
+
+    // Player 1 may resign even while it is player 2's turn.
+    const gameFinished = await resign(gamePda, player1);
+    expect(gameFinished.winner.toBase58()).to.equal(
+      player2.keypair.publicKey.toBase58()
+    );
+    expect(gameFinished.reason).to.deep.equal({ forfeit: {} });
+    expect(gameFinished.turnCount).to.equal(1);
+
+    // Both fresh wallets have exactly this game on their profiles.
+    const profile1 = await program.account.playerProfile.fetch(
+      getProfilePda(player1.keypair.publicKey)
+    );
+    expect(profile1.gamesPlayed).to.equal(1);
+    expect(profile1.losses).to.equal(1);
+    expect(profile1.forfeits).to.equal(1);
+    expect(profile1.shots).to.equal(1);
+    expect(profile1.hits).to.equal(1);
+    expect(profile1.accuracyBps).to.equal(10_000);
+    const profile2 = await program.account.playerProfile.fetch(
+      getProfilePda(player2.keypair.publicKey)
+    );
+    expect(profile2.gamesPlayed).to.equal(1);
+    expect(profile2.wins).to.equal(1);
+    expect(profile2.shots).to.equal(0);
+
+    // Anyone may now audit both fleets, the hit at (0, 0) included.
+    const computationOffset = new anchor.BN(randomBytes(8), "hex");
+    const fleetsRevealedPromise = awaitEvent("fleetsRevealed");
+    await program.methods
+      .revealFleetsIx(computationOffset)
+      .accountsPartial({
+        payer: owner.publicKey,
+        gameAccount: gamePda,
+        ...queueAccounts(computationOffset, "reveal_fleets"),
+      })
+      .signers([owner])
+      .rpc({ skipPreflight: true, commitment: "confirmed" });
+    await finalize(computationOffset);
+    const fleetsRevealed = await fleetsRevealedPromise;
+    expect(fleetsRevealed.player1Fleet).to.deep.equal(FLEET_CELLS);
+    expect(fleetsRevealed.player2Fleet).to.deep.equal(FLEET_CELLS);
+    expect(fleetsRevealed.player1CommitmentValid).to.equal(true);
+    expect(fleetsRevealed.player2CommitmentValid).to.equal(true);
+
+    try {
+      await resign(gamePda, player2);
+      expect.fail("resigned from a finished game");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("InvalidGameState");
+    }
+  });
+
+  it("awards the game to the player left waiting past the deadline", async () => {
+    const player1 = newPlayer(await fundedKeypair());
+    const player2 = newPlayer(await fundedKeypair());
+    const gamePda = await startGame(player1, player2, {
+      moveTimeout: SHORT_TIMEOUT_SECS,
+    });
+
+    await placeShips(gamePda, player1);
+    await waitPastDeadline();
+
+    // Player 2 is the one stalling, so only player 1 may claim.
+    try {
+      await claimTimeout(gamePda, player2);
+      expect.fail("the stalling player claimed the timeout");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("NotWaitingOnOpponent");
+    }
+
+    const gameFinishedPromise = awaitEvent("gameFinished");
+    await claimTimeout(gamePda, player1);
+    const gameFinished = await gameFinishedPromise;
+    expect(gameFinished.winner.toBase58()).to.equal(
+      player1.keypair.publicKey.toBase58()
+    );
+    expect(gameFinished.reason).to.deep.equal({ timeout: {} });
+  });
+
+  it("refuses a timeout claim before the deadline", async () => {
+    const player1 = newPlayer(await fundedKeypair());
+    const player2 = newPlayer(await fundedKeypair());
+    const gamePda = await startGame(player1, player2);
+
+    await placeShips(gamePda, player1);
+    try {
+      await claimTimeout(gamePda, player1);
+      expect.fail("claimed a timeout before the deadline");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("DeadlineNotReached");
+    }
+  });
+
+  it("cancels a game neither player placed in time", async () => {
+    const player1 = newPlayer(await fundedKeypair());
+    const player2 = newPlayer(await fundedKeypair());
+    const gamePda = await startGame(player1, player2, {
+      moveTimeout: SHORT_TIMEOUT_SECS,
+    });
+
+    await waitPastDeadline();
+    const gameCancelledPromise = awaitEvent("gameCancelled");
+    await claimTimeout(gamePda, player2);
+    const gameCancelled = await gameCancelledPromise;
+    expect(gameCancelled.cancelledBy.toBase58()).to.equal(
+      player2.keypair.publicKey.toBase58()
+    );
+
+    const game = await program.account.gameData.fetch(gamePda);
+    expect(game.gameState).to.deep.equal({ cancelled: {} });
+    expect(game.winner).to.equal(null);
+  });
+
+  it("keeps the turn count, deadline and result on the game", async () => {
+    const player1 = newPlayer(await fundedKeypair());
+    const player2 = newPlayer(await fundedKeypair());
+    const gamePda = await startGame(player1, player2);
+
+    await placeShips(gamePda, player1);
+    await placeShips(gamePda, player2);
+    let game = await program.account.gameData.fetch(gamePda);
+    expect(game.turnCount).to.equal(0);
+    expect(game.moveTimeout.toNumber()).to.equal(MOVE_TIMEOUT_SECS);
+    const battleDeadline = game.moveDeadline.toNumber();
+
+    // Every resolved turn restarts the clock for the next player.
+    await takeTurn(gamePda, player1, 9, 9);
+    game = await program.account.gameData.fetch(gamePda);
+    expect(game.turnCount).to.equal(1);
+    expect(game.moveDeadline.toNumber()).to.be.at.least(battleDeadline);
+    expect(game.winner).to.equal(null);
+    expect(game.finishReason).to.equal(null);
+    expect(game.finishedAt.toNumber()).to.equal(0);
+
+    const gameFinished = await resign(gamePda, player2);
+    game = await program.account.gameData.fetch(gamePda);
+    expect(game.gameState).to.deep.equal({ finished: {} });
+    expect(game.winner.toBase58()).to.equal(
+      player1.keypair.publicKey.toBase58()
+    );
+    expect(game.finishReason).to.deep.equal({ forfeit: {} });
+    expect(game.turnCount).to.equal(1);
+    expect(game.finishedAt.toNumber()).to.equal(
+      gameFinished.finishedAt.toNumber()
+    );
+    // The last deadline was set by the turn, before the game ended.
+    expect(game.finishedAt.toNumber()).to.be.at.least(
+      game.moveDeadline.toNumber() - MOVE_TIMEOUT_SECS
+    );
+  });
+
+  it("lets the invitee decline before any computation is queued", async () => {
+    const player1 = newPlayer(owner);
+    const player2 = newPlayer(await fundedKeypair());
+
+    try {
+      await inviteOpponent(player1, newPlayer(owner));
+      expect.fail("invited themselves");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("UnauthorizedPlayer");
+    }
+
+    const gamePda = await inviteOpponent(player1, player2);
+
+    let game = await program.account.gameData.fetch(gamePda);
+    expect(game.gameState).to.deep.equal({ invitePending: {} });
+
+    await program.methods
+      .declineInvite()
+      .accountsPartial({
+        player: player2.keypair.publicKey,
+        gameAccount: gamePda,
+      })
+      .signers([player2.keypair])
+      .rpc({ commitment: "confirmed" });
+
+    game = await program.account.gameData.fetch(gamePda);
+    expect(game.gameState).to.deep.equal({ cancelled: {} });
+  });
+
+  it("lets any wallet join an open game", async () => {
+    const player1 = newPlayer(owner);
+    const player2 = newPlayer(await fundedKeypair());
+
+    const gameId = new anchor.BN(randomBytes(8), "hex");
+    const gamePda = getGamePda(player1.keypair.publicKey, gameId);
+    const openGame = (boardSize: number) =>
+      program.methods
+        .openGame(
+          gameId,
+          Array.from(player1.arciumPublicKey),
+          new anchor.BN(MOVE_TIMEOUT_SECS),
+          new anchor.BN(0),
+          false,
+          boardSize,
+          { classic: {} }
+        )
+        .accountsPartial({
+          payer: player1.keypair.publicKey,
+          gameAccount: gamePda,
+        })
+        .signers([player1.keypair])
+        .rpc({ commitment: "confirmed" });
+
+    try {
+      await openGame(17);
+      expect.fail("opened a game on a 17x17 board");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("InvalidBoardSize");
+    }
+    // `FLEET` fits the smallest board.
+    await openGame(8);
+
+    let game = await program.account.gameData.fetch(gamePda);
+    expect(game.gameState).to.deep.equal({ waitingForOpponent: {} });
+    expect(game.boardSize).to.equal(8);
+
+    const computationOffset = new anchor.BN(randomBytes(8), "hex");
+    const gameCreatedPromise = awaitEvent("gameCreated");
+    await program.methods
+      .joinGame(
         computationOffset,
-        Array.from(ciphertext[0]),
-        Array.from(ciphertext[1]),
-        Array.from(publicKey),
-        new anchor.BN(deserializeLE(nonce).toString())
+        Array.from(player2.arciumPublicKey),
+        new anchor.BN(deserializeLE(randomBytes(16)).toString())
       )
       .accountsPartial({
-        computationAccount: getComputationAccAddress(
-          program.programId,
-          computationOffset
-        ),
-        clusterAccount: arciumEnv.arciumClusterPubkey,
-        mxeAccount: getMXEAccAddress(program.programId),
-        mempoolAccount: getMempoolAccAddress(program.programId),
-        executingPool: getExecutingPoolAccAddress(program.programId),
-        compDefAccount: getCompDefAccAddress(
-          program.programId,
-          Buffer.from(getCompDefAccOffset("add_together")).readUInt32LE()
-        ),
+        payer: player2.keypair.publicKey,
+        gameAccount: gamePda,
+        ...queueAccounts(computationOffset, "init_player_ship_fleet_location"),
       })
+      .signers([player2.keypair])
       .rpc({ skipPreflight: true, commitment: "confirmed" });
-    console.log("Queue sig is ", queueSig);
+    await finalize(computationOffset);
+
+    const gameCreated = await gameCreatedPromise;
+    expect(gameCreated.player2.toBase58()).to.equal(
+      player2.keypair.publicKey.toBase58()
+    );
+    game = await program.account.gameData.fetch(gamePda);
+    expect(game.gameState).to.deep.equal({ placingShips: {} });
+
+    await placeShips(gamePda, player2);
+  });
+
+  it("pays the pot of a wagered game to the winner", async () => {
+    const player1 = newPlayer(await fundedKeypair());
+    const player2 = newPlayer(await fundedKeypair());
+    const gamePda = await startGame(player1, player2, { wager: WAGER });
 
+    const escrowPda = getEscrowPda(gamePda);
+    const escrowRent =
+      await provider.connection.getMinimumBalanceForRentExemption(8 + 1);
+    expect(await provider.connection.getBalance(escrowPda)).to.equal(
+      escrowRent + 2 * WAGER.toNumber()
+    );
+
+    await resign(gamePda, player2);
+
+    // Closing before the pot is claimed would strand it.
+    try {
+      await program.methods
+        .closeGame()
+        .accountsPartial({
+          player: player1.keypair.publicKey,
+          player1: player1.keypair.publicKey,
+          gameAccount: gamePda,
+        })
+        .signers([player1.keypair])
+        .rpc({ commitment: "confirmed" });
+      expect.fail("closed a game with an unclaimed pot");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("WagerUnsettled");
+    }
+
+    const winningsClaimedPromise = awaitEvent("winningsClaimed");
+    await program.methods
+      .claimWinnings()
+      .accountsPartial({
+        winner: player1.keypair.publicKey,
+        gameAccount: gamePda,
+        treasury,
+      })
+      .signers([player1.keypair])
+      .rpc({ commitment: "confirmed" });
+    const winningsClaimed = await winningsClaimedPromise;
+    expect(winningsClaimed.fee.toNumber()).to.equal(potFee);
+    expect(winningsClaimed.amount.toNumber()).to.equal(
+      2 * WAGER.toNumber() - potFee
+    );
+    expect(await provider.connection.getBalance(escrowPda)).to.equal(
+      escrowRent
+    );
+    expect(await provider.connection.getBalance(treasury)).to.be.at.least(
+      potFee
+    );
+  });
+
+  it("refunds the wager of a declined invite", async () => {
+    const player1 = newPlayer(await fundedKeypair());
+    const player2 = newPlayer(await fundedKeypair());
+    const gamePda = await inviteOpponent(player1, player2, { wager: WAGER });
+
+    await program.methods
+      .declineInvite()
+      .accountsPartial({
+        player: player2.keypair.publicKey,
+        gameAccount: gamePda,
+      })
+      .signers([player2.keypair])
+      .rpc({ commitment: "confirmed" });
+
+    const balanceBefore = await provider.connection.getBalance(
+      player1.keypair.publicKey
+    );
+    // Player 2 never deposited, so only player 1 is refunded.
+    await program.methods
+      .refundWager()
+      .accountsPartial({
+        player1: player1.keypair.publicKey,
+        player2: null,
+        gameAccount: gamePda,
+      })
+      .rpc({ commitment: "confirmed" });
+    expect(
+      await provider.connection.getBalance(player1.keypair.publicKey)
+    ).to.equal(balanceBefore + WAGER.toNumber());
+  });
+
+  it("escrows an SPL token wager", async () => {
+    const player1 = newPlayer(await fundedKeypair());
+    const player2 = newPlayer(await fundedKeypair());
+    const wagerMint = await createMint(
+      provider.connection,
+      owner,
+      owner.publicKey,
+      null,
+      6
+    );
+    const player1Tokens = await fundedTokenAccount(
+      wagerMint,
+      player1.keypair.publicKey,
+      WAGER.muln(2)
+    );
+    const player2Tokens = await fundedTokenAccount(
+      wagerMint,
+      player2.keypair.publicKey,
+      WAGER
+    );
+    const treasuryTokens = (
+      await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        owner,
+        wagerMint,
+        treasury
+      )
+    ).address;
+    const tokenBalance = async (address: PublicKey) =>
+      Number((await getAccount(provider.connection, address)).amount);
+
+    // Player 2 wins the first game by resignation and takes the pot.
+    const gamePda = await startGame(player1, player2, {
+      wager: WAGER,
+      wagerMint,
+    });
+    const { wagerVault } = tokenWagerAccounts(gamePda, wagerMint);
+    expect(await tokenBalance(wagerVault)).to.equal(2 * WAGER.toNumber());
+
+    await resign(gamePda, player1);
+    await program.methods
+      .claimWinnings()
+      .accountsPartial({
+        winner: player2.keypair.publicKey,
+        gameAccount: gamePda,
+        winnerTokenAccount: player2Tokens,
+        treasury,
+        treasuryTokenAccount: treasuryTokens,
+        ...tokenWagerAccounts(gamePda, wagerMint),
+      })
+      .signers([player2.keypair])
+      .rpc({ commitment: "confirmed" });
+    expect(await tokenBalance(wagerVault)).to.equal(0);
+    expect(await tokenBalance(player2Tokens)).to.equal(
+      2 * WAGER.toNumber() - potFee
+    );
+    expect(await tokenBalance(treasuryTokens)).to.equal(potFee);
+
+    // Tokens sent to the vault after settlement go to player 1 on close.
+    const stray = 1_000;
+    await mintTo(
+      provider.connection,
+      owner,
+      wagerMint,
+      wagerVault,
+      owner,
+      stray
+    );
+    await program.methods
+      .closeGame()
+      .accountsPartial({
+        player: player2.keypair.publicKey,
+        player1: player1.keypair.publicKey,
+        gameAccount: gamePda,
+        player1TokenAccount: player1Tokens,
+        ...tokenWagerAccounts(gamePda, wagerMint),
+      })
+      .signers([player2.keypair])
+      .rpc({ commitment: "confirmed" });
+    expect(await provider.connection.getAccountInfo(wagerVault)).to.equal(
+      null
+    );
+    expect(await tokenBalance(player1Tokens)).to.equal(
+      WAGER.toNumber() + stray
+    );
+
+    // The second invite is cancelled and player 1's stake comes back.
+    const cancelledPda = await inviteOpponent(player1, player2, {
+      wager: WAGER,
+      wagerMint,
+    });
+    expect(await tokenBalance(player1Tokens)).to.equal(stray);
+    await program.methods
+      .cancelInvite()
+      .accountsPartial({
+        player: player1.keypair.publicKey,
+        gameAccount: cancelledPda,
+      })
+      .signers([player1.keypair])
+      .rpc({ commitment: "confirmed" });
+    await program.methods
+      .refundWager()
+      .accountsPartial({
+        player1: player1.keypair.publicKey,
+        player2: null,
+        gameAccount: cancelledPda,
+        player1TokenAccount: player1Tokens,
+        player2TokenAccount: null,
+        ...tokenWagerAccounts(cancelledPda, wagerMint),
+      })
+      .rpc({ commitment: "confirmed" });
+    expect(await tokenBalance(player1Tokens)).to.equal(
+      WAGER.toNumber() + stray
+    );
+  });
+
+  it("lets only the admin update the config", async () => {
+    const updateConfig = (
+      authority: anchor.web3.Keypair,
+      allowedModes: number,
+      feeTreasury = treasury
+    ) =>
+      program.methods
+        .updateConfig(
+          FEE_BPS,
+          new anchor.BN(MOVE_TIMEOUT_SECS),
+          allowedModes,
+          false
+        )
+        .accounts({ authority: authority.publicKey, treasury: feeTreasury })
+        .signers([authority])
+        .rpc({ commitment: "confirmed" });
+
+    try {
+      await updateConfig(await fundedKeypair(), 0);
+      expect.fail("a stranger updated the config");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("UnauthorizedAdmin");
+    }
+
+    // An empty treasury could never receive a fee below the rent minimum.
+    try {
+      await updateConfig(
+        owner,
+        ALL_MODES,
+        anchor.web3.Keypair.generate().publicKey
+      );
+      expect.fail("set an empty treasury");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("TreasuryNotRentExempt");
+    }
+
+    // Only the upgrade authority may create the config in the first place.
+    const stranger = await fundedKeypair();
+    try {
+      await program.methods
+        .initConfig(0, new anchor.BN(1), ALL_MODES)
+        .accountsPartial({
+          authority: stranger.publicKey,
+          programData,
+          treasury: stranger.publicKey,
+        })
+        .signers([stranger])
+        .rpc({ commitment: "confirmed" });
+      expect.fail("a stranger created the config");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("UnauthorizedAdmin");
+    }
+
+    // With wagers disabled only friendly games can be created.
+    const opponent = newPlayer(await fundedKeypair());
+    await updateConfig(owner, MODE_INVITE | MODE_OPEN);
+    try {
+      await inviteOpponent(newPlayer(owner), opponent, { wager: WAGER });
+      expect.fail("created a wagered game");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("ModeDisabled");
+    }
+    await inviteOpponent(newPlayer(owner), opponent);
+    await updateConfig(owner, ALL_MODES);
+  });
+
+  it("hands the config over in two steps", async () => {
+    const newAdmin = await fundedKeypair();
+    const proposeAuthority = (
+      authority: anchor.web3.Keypair,
+      newAuthority: PublicKey
+    ) =>
+      program.methods
+        .proposeAuthority(newAuthority)
+        .accounts({ authority: authority.publicKey })
+        .signers([authority])
+        .rpc({ commitment: "confirmed" });
+    const acceptAuthority = (newAuthority: anchor.web3.Keypair) =>
+      program.methods
+        .acceptAuthority()
+        .accounts({ newAuthority: newAuthority.publicKey })
+        .signers([newAuthority])
+        .rpc({ commitment: "confirmed" });
+
+    await proposeAuthority(owner, newAdmin.publicKey);
+    // Proposing changes nothing until the new admin accepts.
+    let config = await program.account.programConfig.fetch(getConfigPda());
+    expect(config.authority.toBase58()).to.equal(owner.publicKey.toBase58());
+    expect(config.pendingAuthority.toBase58()).to.equal(
+      newAdmin.publicKey.toBase58()
+    );
+
+    try {
+      await acceptAuthority(await fundedKeypair());
+      expect.fail("a stranger accepted the config");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("UnauthorizedAdmin");
+    }
+
+    await acceptAuthority(newAdmin);
+    config = await program.account.programConfig.fetch(getConfigPda());
+    expect(config.authority.toBase58()).to.equal(
+      newAdmin.publicKey.toBase58()
+    );
+    expect(config.pendingAuthority).to.equal(null);
+
+    // Hand it back for the remaining tests.
+    await proposeAuthority(newAdmin, owner.publicKey);
+    await acceptAuthority(owner);
+  });
+
+  it("pauses new games but still lets players resign", async () => {
+    const player1 = newPlayer(owner);
+    const player2 = newPlayer(await fundedKeypair());
+    const gamePda = await startGame(player1, player2);
+    // One game mid-battle and one invite waiting to be accepted.
+    const player3 = newPlayer(await fundedKeypair());
+    const player4 = newPlayer(await fundedKeypair());
+    const battlePda = await startGame(player3, player4);
+    await placeShips(battlePda, player3);
+    await placeShips(battlePda, player4);
+    const invitee = newPlayer(await fundedKeypair());
+    const invitePda = await inviteOpponent(player3, invitee);
+
+    const pauseToggledPromise = awaitEvent("pauseToggled");
+    await setPaused(true);
+    expect((await pauseToggledPromise).paused).to.equal(true);
+
+    try {
+      await inviteOpponent(player1, player2);
+      expect.fail("created a game while paused");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("ProgramPaused");
+    }
+    try {
+      await acceptInvite(invitePda, invitee);
+      expect.fail("accepted an invite while paused");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("ProgramPaused");
+    }
+    try {
+      await queuePlacement(gamePda, player1, "classic", FLEET);
+      expect.fail("placed a fleet while paused");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("ProgramPaused");
+    }
+    try {
+      await takeTurn(battlePda, player3, 0, 0);
+      expect.fail("took a turn while paused");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("ProgramPaused");
+    }
+
+    const gameFinished = await resign(gamePda, player2);
+    expect(gameFinished.reason).to.deep.equal({ forfeit: {} });
+
+    // Resuming through update_config is announced just the same.
+    const resumedPromise = awaitEvent("pauseToggled");
+    await program.methods
+      .updateConfig(
+        FEE_BPS,
+        new anchor.BN(MOVE_TIMEOUT_SECS),
+        ALL_MODES,
+        false
+      )
+      .accounts({ authority: owner.publicKey, treasury })
+      .signers([owner])
+      .rpc({ commitment: "confirmed" });
+    expect((await resumedPromise).paused).to.equal(false);
+  });
+
+  it("freezes the move deadline while paused", async () => {
+    const player1 = newPlayer(await fundedKeypair());
+    const player2 = newPlayer(await fundedKeypair());
+    const gamePda = await startGame(player1, player2, {
+      moveTimeout: PAUSE_TIMEOUT_SECS,
+    });
+
+    // Nobody can place while paused, so the pause must not use up the clock.
+    await setPaused(true);
+    await new Promise((resolve) =>
+      setTimeout(resolve, (PAUSE_TIMEOUT_SECS + 2) * 1000)
+    );
+    await setPaused(false);
+
+    try {
+      await claimTimeout(gamePda, player2);
+      expect.fail("claimed a deadline that lapsed while paused");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("DeadlineNotReached");
+    }
+  });
+
+  it("moves Elo ratings after a ranked game", async () => {
+    const player1 = newPlayer(await fundedKeypair());
+    const player2 = newPlayer(await fundedKeypair());
+    const gamePda = await startGame(player1, player2, { ranked: true });
+
+    // Equal ratings expect a draw, so the winner takes half of K = 32.
+    const gameFinished = await resign(gamePda, player1);
+    expect(gameFinished.ratingChange).to.equal(16);
+    expect(gameFinished.player1Rating).to.equal(1184);
+    expect(gameFinished.player2Rating).to.equal(1216);
+
+    const profile2 = await program.account.playerProfile.fetch(
+      getProfilePda(player2.keypair.publicKey)
+    );
+    expect(profile2.rating).to.equal(1216);
+  });
+
+  function newPlayer(keypair: anchor.web3.Keypair): Player {
+    const privateKey = x25519.utils.randomPrivateKey();
+    const sharedSecret = x25519.getSharedSecret(privateKey, mxePublicKey);
+    return {
+      keypair,
+      arciumPublicKey: x25519.getPublicKey(privateKey),
+      cipher: new RescueCipher(sharedSecret),
+    };
+  }
+
+  async function fundedKeypair(): Promise<anchor.web3.Keypair> {
+    const keypair = anchor.web3.Keypair.generate();
+    const sig = await provider.connection.requestAirdrop(
+      keypair.publicKey,
+      10 * anchor.web3.LAMPORTS_PER_SOL
+    );
+    await provider.connection.confirmTransaction(sig, "confirmed");
+    return keypair;
+  }
+
+  function getGamePda(player1: PublicKey, gameId: anchor.BN): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("game_data_account"),
+        player1.toBuffer(),
+        gameId.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    )[0];
+  }
+
+  function getProfilePda(player: PublicKey): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("player_profile"), player.toBuffer()],
+      program.programId
+    )[0];
+  }
+
+  function getConfigPda(): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("program_config")],
+      program.programId
+    )[0];
+  }
+
+  function getEscrowPda(gamePda: PublicKey): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("wager_escrow"), gamePda.toBuffer()],
+      program.programId
+    )[0];
+  }
+
+  function tokenWagerAccounts(gamePda: PublicKey, wagerMint: PublicKey) {
+    return {
+      wagerMint,
+      wagerVault: getAssociatedTokenAddressSync(wagerMint, gamePda, true),
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+  }
+
+  async function fundedTokenAccount(
+    wagerMint: PublicKey,
+    holder: PublicKey,
+    amount: anchor.BN
+  ): Promise<PublicKey> {
+    const tokenAccount = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      owner,
+      wagerMint,
+      holder
+    );
+    await mintTo(
+      provider.connection,
+      owner,
+      wagerMint,
+      tokenAccount.address,
+      owner,
+      BigInt(amount.toString())
+    );
+    return tokenAccount.address;
+  }
+
+  function queueAccounts(computationOffset: anchor.BN, circuit: string) {
+    return {
+      computationAccount: getComputationAccAddress(
+        program.programId,
+        computationOffset
+      ),
+      clusterAccount: arciumEnv.arciumClusterPubkey,
+      mxeAccount: getMXEAccAddress(program.programId),
+      mempoolAccount: getMempoolAccAddress(program.programId),
+      executingPool: getExecutingPoolAccAddress(program.programId),
+      compDefAccount: getCompDefAccAddress(
+        program.programId,
+        Buffer.from(getCompDefAccOffset(circuit)).readUInt32LE()
+      ),
+    };
+  }
+
+  async function finalize(computationOffset: anchor.BN) {
     const finalizeSig = await awaitComputationFinalization(
       provider as anchor.AnchorProvider,
       computationOffset,
@@ -109,22 +1131,230 @@ describe("ConfidentialBattleshipGame", () => {
       "confirmed"
     );
     console.log("Finalize sig is ", finalizeSig);
+  }
 
-    const sumEvent = await sumEventPromise;
-    const decrypted = cipher.decrypt([sumEvent.sum], sumEvent.nonce)[0];
-    expect(decrypted).to.equal(val1 + val2);
-  });
+  async function inviteOpponent(
+    player1: Player,
+    player2: Player,
+    {
+      wager = new anchor.BN(0),
+      wagerMint,
+      ranked = false,
+      boardSize = 10,
+      fleetPreset = "classic",
+      moveTimeout = MOVE_TIMEOUT_SECS,
+    }: GameOptions = {}
+  ): Promise<PublicKey> {
+    const gameId = new anchor.BN(randomBytes(8), "hex");
+    const gamePda = getGamePda(player1.keypair.publicKey, gameId);
+    const tokenAccounts = wagerMint
+      ? {
+          ...tokenWagerAccounts(gamePda, wagerMint),
+          payerTokenAccount: getAssociatedTokenAddressSync(
+            wagerMint,
+            player1.keypair.publicKey
+          ),
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        }
+      : {};
+
+    await program.methods
+      .newGame(
+        gameId,
+        player2.keypair.publicKey,
+        Array.from(player1.arciumPublicKey),
+        Array.from(player2.arciumPublicKey),
+        new anchor.BN(moveTimeout),
+        wager,
+        ranked,
+        boardSize,
+        { [fleetPreset]: {} } as FleetPresetArg
+      )
+      .accountsPartial({
+        payer: player1.keypair.publicKey,
+        gameAccount: gamePda,
+        ...tokenAccounts,
+      })
+      .signers([player1.keypair])
+      .rpc({ commitment: "confirmed" });
+
+    return gamePda;
+  }
+
+  async function acceptInvite(
+    gamePda: PublicKey,
+    player2: Player,
+    wagerMint?: PublicKey
+  ) {
+    const tokenAccounts = wagerMint
+      ? {
+          ...tokenWagerAccounts(gamePda, wagerMint),
+          payerTokenAccount: getAssociatedTokenAddressSync(
+            wagerMint,
+            player2.keypair.publicKey
+          ),
+        }
+      : {};
+    const computationOffset = new anchor.BN(randomBytes(8), "hex");
+    const gameCreatedPromise = awaitEvent("gameCreated");
+    await program.methods
+      .acceptInvite(
+        computationOffset,
+        Array.from(player2.arciumPublicKey),
+        new anchor.BN(deserializeLE(randomBytes(16)).toString())
+      )
+      .accountsPartial({
+        payer: player2.keypair.publicKey,
+        gameAccount: gamePda,
+        ...queueAccounts(computationOffset, "init_player_ship_fleet_location"),
+        ...tokenAccounts,
+      })
+      .signers([player2.keypair])
+      .rpc({ skipPreflight: true, commitment: "confirmed" });
+
+    await finalize(computationOffset);
+    const gameCreated = await gameCreatedPromise;
+    expect(gameCreated.gamePda.toBase58()).to.equal(gamePda.toBase58());
+  }
+
+  async function startGame(
+    player1: Player,
+    player2: Player,
+    options: GameOptions = {}
+  ): Promise<PublicKey> {
+    const gamePda = await inviteOpponent(player1, player2, options);
+    await acceptInvite(gamePda, player2, options.wagerMint);
+    return gamePda;
+  }
+
+  async function placeShips(
+    gamePda: PublicKey,
+    player: Player,
+    fleetPreset: FleetPreset = "classic"
+  ) {
+    const shipsPlacedPromise = awaitEvent("shipsPlaced");
+    await queuePlacement(gamePda, player, fleetPreset, FLEET);
+    return await shipsPlacedPromise;
+  }
+
+  // Encrypts `fleet`, queues `place_ships` and waits for its callback.
+  async function queuePlacement(
+    gamePda: PublicKey,
+    player: Player,
+    fleetPreset: FleetPreset,
+    fleet: typeof FLEET
+  ) {
+    const nonce = randomBytes(16);
+    const salt = randomBytes(16);
+    const plaintext = fleet.flatMap((ship) => [
+      BigInt(ship.row * 16 + ship.col),
+      BigInt(ship.vertical ? 1 : 0),
+    ]);
+    plaintext.push(deserializeLE(salt));
+    const ciphertext = player.cipher.encrypt(plaintext, nonce);
+    const encryptedShips = fleet.map((_, i) =>
+      ciphertext.slice(2 * i, 2 * i + 2).map((c) => Array.from(c))
+    );
+    const encryptedSalt = Array.from(ciphertext[2 * fleet.length]);
+    // Published now, checked against the decrypted fleet by `reveal_fleets`.
+    const commitment = createHash("sha256")
+      .update(Buffer.from(fleetCells(fleetPreset, fleet).flat()))
+      .update(salt)
+      .digest();
+
+    const computationOffset = new anchor.BN(randomBytes(8), "hex");
+    await program.methods
+      .placeShipsIx(
+        computationOffset,
+        new anchor.BN(deserializeLE(nonce).toString()),
+        encryptedShips,
+        encryptedSalt,
+        Array.from(commitment)
+      )
+      .accountsPartial({
+        payer: player.keypair.publicKey,
+        gameAccount: gamePda,
+        ...queueAccounts(computationOffset, "place_ships"),
+      })
+      .signers([player.keypair])
+      .rpc({ skipPreflight: true, commitment: "confirmed" });
+
+    await finalize(computationOffset);
+  }
+
+  async function takeTurn(
+    gamePda: PublicKey,
+    player: Player,
+    row: number,
+    col: number
+  ) {
+    const computationOffset = new anchor.BN(randomBytes(8), "hex");
+    const turnResultPromise = awaitEvent("turnResult");
+    await program.methods
+      .takeTurnIx(computationOffset, row, col)
+      .accountsPartial({
+        payer: player.keypair.publicKey,
+        gameAccount: gamePda,
+        ...queueAccounts(computationOffset, "take_turn"),
+      })
+      .signers([player.keypair])
+      .rpc({ skipPreflight: true, commitment: "confirmed" });
+
+    await finalize(computationOffset);
+    return await turnResultPromise;
+  }
+
+  async function resign(gamePda: PublicKey, player: Player) {
+    const gameFinishedPromise = awaitEvent("gameFinished");
+    await program.methods
+      .resign()
+      .accountsPartial({
+        player: player.keypair.publicKey,
+        gameAccount: gamePda,
+      })
+      .signers([player.keypair])
+      .rpc({ commitment: "confirmed" });
+
+    return await gameFinishedPromise;
+  }
+
+  async function claimTimeout(gamePda: PublicKey, player: Player) {
+    await program.methods
+      .claimTimeout()
+      .accountsPartial({
+        player: player.keypair.publicKey,
+        gameAccount: gamePda,
+      })
+      .signers([player.keypair])
+      .rpc({ commitment: "confirmed" });
+  }
+
+  async function setPaused(paused: boolean) {
+    await program.methods
+      .setPaused(paused)
+      .accounts({ authority: owner.publicKey })
+      .signers([owner])
+      .rpc({ commitment: "confirmed" });
+  }
+
+  // Waits until the validator clock is past a `SHORT_TIMEOUT_SECS` deadline.
+  async function waitPastDeadline() {
+    await new Promise((resolve) =>
+      setTimeout(resolve, (SHORT_TIMEOUT_SECS + 2) * 1000)
+    );
+  }
 
-  async function initAddTogetherCompDef(
+  async function initCompDef(
     program: Program<ConfidentialBattleshipGame>,
     owner: anchor.web3.Keypair,
+    circuit: string,
     uploadRawCircuit: boolean,
     offchainSource: boolean
   ): Promise<string> {
     const baseSeedCompDefAcc = getArciumAccountBaseSeed(
       "ComputationDefinitionAccount"
     );
-    const offset = getCompDefAccOffset("add_together");
+    const offset = getCompDefAccOffset(circuit);
 
     const compDefPDA = PublicKey.findProgramAddressSync(
       [baseSeedCompDefAcc, program.programId.toBuffer(), offset],
@@ -133,8 +1363,13 @@ describe("ConfidentialBattleshipGame", () => {
 
     console.log("Comp def pda is ", compDefPDA);
 
-    const sig = await program.methods
-      .initAddTogetherCompDef()
+    const methods = {
+      init_player_ship_fleet_location: program.methods.initNewGameCompDef,
+      place_ships: program.methods.initPlaceShipsCompDef,
+      take_turn: program.methods.initTakeTurnCompDef,
+      reveal_fleets: program.methods.initRevealFleetsCompDef,
+    };
+    const sig = await methods[circuit]()
       .accounts({
         compDefAccount: compDefPDA,
         payer: owner.publicKey,
@@ -144,14 +1379,14 @@ describe("ConfidentialBattleshipGame", () => {
       .rpc({
         commitment: "confirmed",
       });
-    console.log("Init add together computation definition transaction", sig);
+    console.log(`Init ${circuit} computation definition transaction`, sig);
 
     if (uploadRawCircuit) {
-      const rawCircuit = fs.readFileSync("build/add_together.arcis");
+      const rawCircuit = fs.readFileSync(`build/${circuit}.arcis`);
 
       await uploadCircuit(
         provider as anchor.AnchorProvider,
-        "add_together",
+        circuit,
         program.programId,
         rawCircuit,
         true